@@ -1,35 +1,331 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use reqwest::{header::HeaderValue, Method};
 use serde::{Deserialize, Serialize};
 
-use crate::{screenshots::ScreenshotType, utils::ascella_dir};
+use crate::{
+    hotkeys::HotkeyConfig,
+    local_save::LocalSaveConfig,
+    pipeline::{OptimizePreset, OutputSettings, Processor},
+    recording::RecordingConfig,
+    redaction::RedactionConfig,
+    retention::RetentionConfig,
+    screenshots::ScreenshotType,
+    sxcu::BodyType,
+    utils::{ascella_dir, expand_home},
+    watermark::WatermarkConfig,
+};
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
-pub struct AscellaConfig {
-    #[serde(default)]
+pub const DEFAULT_API_URL: &str = "https://api.picup.click/api/v3";
+pub const DEFAULT_REQUEST_URL: &str = "https://api.picup.click/api/v3/upload";
+
+/// A named set of uploader settings, one of them is used for uploads at a time
+///
+/// Besides the ascella api settings this holds everything a ShareX custom uploader can describe, empty `url` means
+/// the response is parsed as an ascella upload response
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct UploaderProfile {
+    pub name: String,
     pub api_url: String,
-    #[serde(default)]
     pub api_key: String,
     #[serde(alias = "RequestURL")]
     pub request_url: String,
+    pub request_method: String,
     #[serde(alias = "Headers")]
     pub headers: HashMap<String, String>,
+    pub parameters: HashMap<String, String>,
+    pub body: BodyType,
+    pub file_form_name: String,
+    pub arguments: HashMap<String, String>,
+    pub data: String,
+    pub url: String,
+    pub deletion_url: String,
+    pub error_message: String,
+    /// open the annotation editor before uploading screenshots taken from the gui
+    pub annotate: bool,
+    pub output: OutputSettings,
+    /// save captures made with this profile instead of uploading them
+    pub local_only: bool,
+}
+
+impl Default for UploaderProfile {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            api_url: DEFAULT_API_URL.to_string(),
+            api_key: String::new(),
+            request_url: DEFAULT_REQUEST_URL.to_string(),
+            request_method: "POST".to_string(),
+            headers: HashMap::new(),
+            parameters: HashMap::new(),
+            body: BodyType::MultipartFormData,
+            file_form_name: "file".to_string(),
+            arguments: HashMap::new(),
+            data: String::new(),
+            url: String::new(),
+            deletion_url: String::new(),
+            error_message: String::new(),
+            annotate: false,
+            output: OutputSettings::default(),
+            local_only: false,
+        }
+    }
+}
+
+impl UploaderProfile {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// GET request to the ascella api authenticated with the profile token, `path` is appended to the api url
+    pub fn api_request(&self, path: &str) -> Result<reqwest::Request> {
+        let url = format!("{}{path}", self.api_url);
+        let url = url.parse().with_context(|| format!("Invalid api url {url}"))?;
+        let token = HeaderValue::from_str(&self.api_key).context("Invalid api token, it can't contain newlines")?;
+        let mut req = reqwest::Request::new(Method::GET, url);
+        req.headers_mut().append("ascella-token", token);
+        Ok(req)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AscellaConfig {
+    #[serde(default)]
+    pub profiles: Vec<UploaderProfile>,
+    #[serde(default)]
+    pub default_profile: String,
     pub debug: bool,
     pub s_type: ScreenshotType,
     pub webserver: bool,
     pub theme: u8,
-    pub optimize_png: bool,
-    pub optimize_timeout: u64,
     pub console_logging: bool,
     pub notifications_enabled: bool,
+    /// remove EXIF, XMP and IPTC metadata from uploaded images
+    pub strip_metadata: bool,
+    /// save captures instead of uploading them, whatever the profile says
+    pub local_only: bool,
+    /// where captures are saved before they are uploaded, empty is `~/.ascella/images`
+    pub output_dir: String,
+    /// see `filename` for the tokens
+    pub filename_template: String,
+    #[serde(default)]
+    pub local_save: LocalSaveConfig,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    /// stages applied to screenshots before they are uploaded
+    #[serde(default)]
+    pub pipeline: Vec<Processor>,
+    #[serde(default)]
+    pub watermark: WatermarkConfig,
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub hotkeys: HotkeyConfig,
+
+    // configs from before profiles existed, these get moved into a profile by `migrate`
+    #[serde(default, skip_serializing)]
+    api_url: String,
+    #[serde(default, skip_serializing)]
+    api_key: String,
+    #[serde(default, skip_serializing, alias = "RequestURL")]
+    request_url: String,
+    #[serde(default, skip_serializing, alias = "Headers")]
+    headers: HashMap<String, String>,
+    // replaced by the optimize pipeline stage
+    #[serde(default, skip_serializing)]
+    optimize_png: bool,
+    #[serde(default, skip_serializing)]
+    optimize_timeout: Option<u64>,
 }
 
 impl AscellaConfig {
+    pub fn output_dir(&self) -> PathBuf {
+        match self.output_dir.trim() {
+            "" => ascella_dir().join("images"),
+            dir => expand_home(dir),
+        }
+    }
+
+    /// Whether captures are saved instead of uploaded
+    pub fn is_local_only(&self) -> bool {
+        self.local_only || self.profile().local_only
+    }
+
     pub async fn save(&self) -> Result<()> {
         let file = ascella_dir().join("ascella.toml");
         tokio::fs::write(file, toml_edit::ser::to_string_pretty(self)?).await?;
 
         Ok(())
     }
+
+    /// Makes sure there is at least one profile and that the default profile exists, also moves old settings to
+    /// where they live now
+    pub fn migrate(&mut self) {
+        if self.profiles.is_empty() {
+            let mut profile = UploaderProfile::new("default");
+            if !self.api_url.is_empty() {
+                profile.api_url = std::mem::take(&mut self.api_url);
+            }
+            if !self.request_url.is_empty() {
+                profile.request_url = std::mem::take(&mut self.request_url);
+            }
+            profile.api_key = std::mem::take(&mut self.api_key);
+            profile.headers = std::mem::take(&mut self.headers);
+            self.profiles.push(profile);
+        }
+        if std::mem::take(&mut self.optimize_png)
+            && !self
+                .pipeline
+                .iter()
+                .any(|stage| matches!(stage, Processor::Optimize { .. }))
+        {
+            self.pipeline.insert(
+                0,
+                Processor::Optimize {
+                    timeout_ms: self.optimize_timeout.take().unwrap_or(100),
+                    preset: OptimizePreset::Balanced,
+                },
+            );
+        }
+        // profiles are selected by name, hand edited configs can have empty or repeated ones
+        for i in 0..self.profiles.len() {
+            let name = self.profiles[i].name.clone();
+            if name.trim().is_empty() || self.profiles[..i].iter().any(|p| p.name == name) {
+                self.profiles[i].name = self.free_profile_name();
+            }
+        }
+        if !self.profiles.iter().any(|p| p.name == self.default_profile) {
+            self.default_profile = self.profiles[0].name.clone();
+        }
+    }
+
+    /// `profile N` with the lowest N no profile uses yet
+    fn free_profile_name(&self) -> String {
+        let mut n = self.profiles.len() + 1;
+        while self.profiles.iter().any(|p| p.name == format!("profile {n}")) {
+            n += 1;
+        }
+        format!("profile {n}")
+    }
+
+    fn profile_index(&self) -> usize {
+        self.profiles
+            .iter()
+            .position(|p| p.name == self.default_profile)
+            .unwrap_or(0)
+    }
+
+    /// The profile uploads currently go to
+    pub fn profile(&self) -> &UploaderProfile {
+        &self.profiles[self.profile_index()]
+    }
+
+    pub fn profile_mut(&mut self) -> &mut UploaderProfile {
+        let index = self.profile_index();
+        &mut self.profiles[index]
+    }
+
+    pub fn select_profile(&mut self, name: &str) -> Result<()> {
+        if !self.profiles.iter().any(|p| p.name == name) {
+            let names: Vec<&str> = self.profiles.iter().map(|p| p.name.as_str()).collect();
+            return Err(anyhow!(
+                "No profile named {name}, available profiles: {}",
+                names.join(", ")
+            ));
+        }
+        self.default_profile = name.to_string();
+        Ok(())
+    }
+
+    /// Checks that the current profile can be renamed to `name`, names have to be unique since profiles are selected
+    /// by them
+    pub fn check_profile_name(&self, name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(anyhow!("Profile names can't be empty"));
+        }
+        let current = self.profile_index();
+        if self
+            .profiles
+            .iter()
+            .enumerate()
+            .any(|(i, p)| i != current && p.name == name)
+        {
+            return Err(anyhow!("A profile named {name} already exists"));
+        }
+        Ok(())
+    }
+
+    pub fn rename_profile(&mut self, name: &str) -> Result<()> {
+        self.check_profile_name(name)?;
+        self.profile_mut().name = name.to_string();
+        self.default_profile = name.to_string();
+        Ok(())
+    }
+
+    /// Adds the profile replacing any profile with the same name and selects it
+    pub fn import_profile(&mut self, profile: UploaderProfile) {
+        self.default_profile = profile.name.clone();
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    /// Adds a new profile copied from the current one and selects it
+    pub fn add_profile(&mut self) {
+        let profile = UploaderProfile {
+            name: self.free_profile_name(),
+            ..self.profile().clone()
+        };
+        self.default_profile = profile.name.clone();
+        self.profiles.push(profile);
+    }
+
+    /// Removes the current profile, the last profile can't be removed
+    pub fn remove_profile(&mut self) {
+        if self.profiles.len() > 1 {
+            self.profiles.remove(self.profile_index());
+            self.default_profile = self.profiles[0].name.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(names: &[&str]) -> AscellaConfig {
+        AscellaConfig {
+            profiles: names.iter().map(|name| UploaderProfile::new(*name)).collect(),
+            default_profile: names[0].to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn migrate_renames_empty_and_duplicate_profiles() {
+        let mut config = config(&["work", "", "work"]);
+        config.migrate();
+        let names: Vec<&str> = config.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["work", "profile 4", "profile 5"]);
+        assert_eq!(config.default_profile, "work");
+    }
+
+    #[test]
+    fn rename_rejects_empty_and_taken_names() {
+        let mut config = config(&["work", "home"]);
+        assert!(config.rename_profile(" ").is_err());
+        assert!(config.rename_profile("home").is_err());
+        assert!(config.rename_profile("work").is_ok());
+        config.rename_profile("office").unwrap();
+        assert_eq!(config.profile().name, "office");
+        assert_eq!(config.default_profile, "office");
+    }
 }
@@ -7,6 +7,15 @@ use clap::{Parser, Subcommand};
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 pub struct AscellaCli {
+    /// Name of the uploader profile to use instead of the default one, only for subcommands
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// Upload images with their EXIF, XMP and IPTC metadata, only for subcommands
+    #[arg(long, global = true)]
+    pub keep_metadata: bool,
+    /// Save captures locally instead of uploading them, only for subcommands
+    #[arg(long, global = true)]
+    pub no_upload: bool,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -19,6 +28,30 @@ pub enum Commands {
     Window { delay: Option<u64> },
     /// Screenshot a screen
     Screen { delay: Option<u64> },
+    /// Record the screen until ctrl+c is pressed and upload the recording
+    Record {
+        /// Select an area to record instead of the whole screen
+        #[arg(short, long)]
+        area: bool,
+        /// Stop after this many seconds
+        #[arg(short, long)]
+        duration: Option<u64>,
+    },
     /// Upload a file
     Upload { file: PathBuf },
+    /// Upload the image or text in the clipboard
+    Clipboard,
+    /// Remove old captures according to the retention policy in the config
+    Cleanup {
+        /// Only list what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Delete an upload from the server using its url or history id
+    Delete {
+        target: String,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
 }
@@ -1,3 +1,46 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::{ascella_config::AscellaConfig, filename};
+
+/// The usual file extension for an image mime type, mime_guess sorts them alphabetically and would pick `jpe` for jpeg
+fn image_extension(mime: &str) -> &'static str {
+    image::ImageFormat::from_mime_type(mime)
+        .and_then(|format| format.extensions_str().first().copied())
+        .or_else(|| mime_guess::get_mime_extensions_str(mime).and_then(|exts| exts.first().copied()))
+        .unwrap_or("png")
+}
+
+/// Contents read from the clipboard
+pub enum ClipboardContent {
+    Image { data: Vec<u8>, mime: String },
+    Text(String),
+}
+
+impl ClipboardContent {
+    /// Saves the contents to a file so it can be uploaded, named like captures in the output directory
+    pub async fn save(self, config: &AscellaConfig) -> Result<PathBuf> {
+        let (path, data) = match self {
+            Self::Image { data, mime } => (
+                filename::capture_path(config, "clipboard", image_extension(&mime)).await?,
+                data,
+            ),
+            Self::Text(text) => (
+                filename::capture_path(config, "clipboard", "txt").await?,
+                text.into_bytes(),
+            ),
+        };
+        tokio::fs::write(&path, data).await?;
+        Ok(path)
+    }
+}
+
+/// Reads the clipboard and saves it to a file ready for upload
+pub async fn paste_to_file(config: &AscellaConfig) -> Result<PathBuf> {
+    paste().await?.save(config).await
+}
+
 #[cfg(not(target_os = "linux"))]
 pub async fn copy(t: String) {
     use clipboard2::{Clipboard, SystemClipboard};
@@ -5,6 +48,43 @@ pub async fn copy(t: String) {
     clipboard.set_string_contents(t).unwrap();
 }
 
+#[cfg(not(target_os = "linux"))]
+pub async fn copy_image(_path: &Path, _mime: &str) -> Result<()> {
+    Err(anyhow!("Copying images is only supported on linux"))
+}
+
+/// Puts the image itself on the clipboard
+#[cfg(target_os = "linux")]
+pub async fn copy_image(path: &Path, mime: &str) -> Result<()> {
+    use std::{env, fs::File, process::Stdio};
+
+    use tokio::process::Command;
+
+    let wayland = env::var("XDG_SESSION_TYPE").is_ok_and(|session| session.eq_ignore_ascii_case("wayland"));
+    let mut command = if wayland {
+        let mut command = Command::new("wl-copy");
+        command.args(["--type", mime]).stdin(File::open(path)?);
+        command
+    } else {
+        let mut command = Command::new("xclip");
+        command
+            .args(["-selection", "clipboard", "-t", mime, "-i"])
+            .arg(path)
+            .stdin(Stdio::null());
+        command
+    };
+    // both fork to keep serving the clipboard, the parent exits right away
+    let status = command
+        .stdout(Stdio::null())
+        .status()
+        .await
+        .map_err(|e| anyhow!("Failed running {} {e}", if wayland { "wl-copy" } else { "xclip" }))?;
+    if !status.success() {
+        return Err(anyhow!("Copying the image failed {status}"));
+    }
+    Ok(())
+}
+
 #[cfg(target_os = "linux")]
 pub async fn copy(t: String) {
     use std::{env, process::Stdio};
@@ -39,3 +119,94 @@ pub async fn copy(t: String) {
         }
     }
 }
+
+#[cfg(not(target_os = "linux"))]
+pub async fn paste() -> Result<ClipboardContent> {
+    use clipboard2::{Clipboard, SystemClipboard};
+    let clipboard = SystemClipboard::new().map_err(|e| anyhow!("Failed opening clipboard {e:?}"))?;
+    let text = clipboard
+        .get_string_contents()
+        .map_err(|e| anyhow!("Failed reading clipboard {e:?}"))?;
+    if text.is_empty() {
+        return Err(anyhow!("Clipboard is empty"));
+    }
+    Ok(ClipboardContent::Text(text))
+}
+
+/// Reads the clipboard preferring images over text using wl-paste or xclip
+#[cfg(target_os = "linux")]
+pub async fn paste() -> Result<ClipboardContent> {
+    use std::env;
+
+    use tokio::process::Command;
+
+    let wayland = match env::var("XDG_SESSION_TYPE") {
+        Ok(ok) => matches!(ok.to_lowercase().as_ref(), "wayland"),
+        Err(_) => false,
+    };
+
+    let read = |target: Option<&str>| {
+        let mut cmd = if wayland {
+            let mut cmd = Command::new("wl-paste");
+            match target {
+                Some("TARGETS") => cmd.arg("--list-types"),
+                Some(target) => cmd.args(["--no-newline", "--type", target]),
+                None => cmd.arg("--no-newline"),
+            };
+            cmd
+        } else {
+            let mut cmd = Command::new("xclip");
+            cmd.args(["-selection", "clipboard", "-o"]);
+            if let Some(target) = target {
+                cmd.args(["-t", target]);
+            }
+            cmd
+        };
+        async move {
+            let output = cmd
+                .output()
+                .await
+                .map_err(|e| anyhow!("Failed running {} {e}", cmd.as_std().get_program().to_string_lossy()))?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Failed reading clipboard {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            Ok(output.stdout)
+        }
+    };
+
+    let targets = String::from_utf8(read(Some("TARGETS")).await?).unwrap_or_default();
+    let images: Vec<&str> = targets
+        .lines()
+        .map(str::trim)
+        .filter(|t| t.starts_with("image/"))
+        .collect();
+    let image = images.iter().find(|t| **t == "image/png").or(images.first());
+    if let Some(mime) = image {
+        return Ok(ClipboardContent::Image {
+            data: read(Some(*mime)).await?,
+            mime: mime.to_string(),
+        });
+    }
+
+    let text = String::from_utf8(read(None).await?)?;
+    if text.is_empty() {
+        return Err(anyhow!("Clipboard is empty"));
+    }
+    Ok(ClipboardContent::Text(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_extensions() {
+        assert_eq!(image_extension("image/jpeg"), "jpg");
+        assert_eq!(image_extension("image/png"), "png");
+        assert_eq!(image_extension("image/webp"), "webp");
+        assert_eq!(image_extension("application/x-unknown"), "png");
+    }
+}
@@ -17,7 +17,6 @@ pub enum Item<'a> {
     // TODO(emilk): add Style here so empty heading still uses up the right amount of space.
     Newline,
 
-    ///
     Text(Style, &'a str),
 
     /// title, url
@@ -0,0 +1,432 @@
+//! Annotation editor shown before uploading a screenshot
+//!
+//! Annotations are kept in image coordinates and only burned into the file when the user uploads, the preview
+//! texture is re-rendered whenever an annotation is added or removed
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use eframe::egui::{
+    self, color_picker::Alpha, pos2, vec2, Color32, Pos2, Rect, Sense, Stroke, TextureHandle, TextureOptions,
+};
+use image::{imageops, Rgba, RgbaImage};
+
+use crate::{ascella_config::AscellaConfig, text::draw_text};
+
+const BLUR_SIGMA: f32 = 8.0;
+const PIXELATE_SIZE: u32 = 12;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Tool {
+    Arrow,
+    Rect,
+    Freehand,
+    Text,
+    Blur,
+    Pixelate,
+    Crop,
+}
+
+impl Tool {
+    const ALL: [Tool; 7] = [
+        Self::Arrow,
+        Self::Rect,
+        Self::Freehand,
+        Self::Text,
+        Self::Blur,
+        Self::Pixelate,
+        Self::Crop,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Arrow => "Arrow",
+            Self::Rect => "Rectangle",
+            Self::Freehand => "Freehand",
+            Self::Text => "Text",
+            Self::Blur => "Blur",
+            Self::Pixelate => "Pixelate",
+            Self::Crop => "Crop",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Annotation {
+    Arrow {
+        from: Pos2,
+        to: Pos2,
+        color: Color32,
+        width: f32,
+    },
+    Rect {
+        rect: Rect,
+        color: Color32,
+        width: f32,
+    },
+    Freehand {
+        points: Vec<Pos2>,
+        color: Color32,
+        width: f32,
+    },
+    Text {
+        pos: Pos2,
+        text: String,
+        color: Color32,
+        size: f32,
+    },
+    Blur(Rect),
+    Pixelate(Rect),
+    Crop(Rect),
+}
+
+pub enum EditorAction {
+    None,
+    Upload,
+    Cancel,
+}
+
+pub struct Editor {
+    pub path: PathBuf,
+    pub config: AscellaConfig,
+    original: RgbaImage,
+    annotations: Vec<Annotation>,
+    tool: Tool,
+    color: Color32,
+    width: f32,
+    text: String,
+    text_size: f32,
+    /// points of the annotation being drawn
+    drag: Option<Vec<Pos2>>,
+    texture: Option<TextureHandle>,
+}
+
+impl Editor {
+    pub fn open(path: PathBuf, config: AscellaConfig) -> Result<Self> {
+        let original = image::open(&path)
+            .map_err(|e| anyhow!("Failed opening {} {e}", path.display()))?
+            .to_rgba8();
+        Ok(Self {
+            path,
+            config,
+            original,
+            annotations: Vec::new(),
+            tool: Tool::Arrow,
+            color: Color32::RED,
+            width: 4.0,
+            text: String::new(),
+            text_size: 24.0,
+            drag: None,
+            texture: None,
+        })
+    }
+
+    /// Writes the annotations into the image file, untouched images are left as they are
+    pub fn save(&self) -> Result<()> {
+        if self.annotations.is_empty() {
+            return Ok(());
+        }
+        let mut image = render(&self.original, &self.annotations);
+        let crop = self.annotations.iter().rev().find_map(|a| match a {
+            Annotation::Crop(rect) => Some(*rect),
+            _ => None,
+        });
+        if let Some((x, y, w, h)) = crop.and_then(|rect| pixel_rect(rect, &image)) {
+            image = imageops::crop_imm(&image, x, y, w, h).to_image();
+        }
+        image.save(&self.path)?;
+        Ok(())
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) -> EditorAction {
+        let mut action = EditorAction::None;
+        let texture = self
+            .texture
+            .get_or_insert_with(|| {
+                let preview = render(&self.original, &self.annotations);
+                ctx.load_texture(
+                    "annotation-preview",
+                    egui::ColorImage::from_rgba_unmultiplied(
+                        [preview.width() as usize, preview.height() as usize],
+                        preview.as_raw(),
+                    ),
+                    TextureOptions::LINEAR,
+                )
+            })
+            .clone();
+
+        egui::Window::new("Annotate")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, vec2(0.0, 10.0))
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    for tool in Tool::ALL {
+                        ui.selectable_value(&mut self.tool, tool, tool.name());
+                    }
+                });
+                ui.horizontal(|ui| {
+                    egui::color_picker::color_edit_button_srgba(ui, &mut self.color, Alpha::Opaque);
+                    ui.add(egui::Slider::new(&mut self.width, 1.0..=20.0).text("Width"));
+                    if self.tool == Tool::Text {
+                        ui.add(egui::TextEdit::singleline(&mut self.text).hint_text("Click the image to place"));
+                        ui.add(egui::DragValue::new(&mut self.text_size).clamp_range(8.0..=128.0));
+                    }
+                    if ui
+                        .add_enabled(!self.annotations.is_empty(), egui::Button::new("Undo"))
+                        .clicked()
+                    {
+                        self.annotations.pop();
+                        self.texture = None;
+                    }
+                });
+
+                self.canvas(ui, &texture);
+
+                ui.horizontal(|ui| {
+                    if ui.button("Upload").clicked() {
+                        action = EditorAction::Upload;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        action = EditorAction::Cancel;
+                    }
+                });
+            });
+        action
+    }
+
+    fn canvas(&mut self, ui: &mut egui::Ui, texture: &TextureHandle) {
+        let (w, h) = (self.original.width() as f32, self.original.height() as f32);
+        let max = (ui.ctx().screen_rect().size() - vec2(60.0, 160.0)).max(vec2(100.0, 100.0));
+        let scale = (max.x / w).min(max.y / h).min(1.0);
+        let (response, painter) = ui.allocate_painter(vec2(w, h) * scale, Sense::click_and_drag());
+        let canvas = response.rect;
+        let to_image = |p: Pos2| {
+            pos2(
+                ((p.x - canvas.min.x) / scale).clamp(0.0, w),
+                ((p.y - canvas.min.y) / scale).clamp(0.0, h),
+            )
+        };
+        let to_screen = |p: Pos2| canvas.min + p.to_vec2() * scale;
+
+        painter.image(
+            texture.id(),
+            canvas,
+            Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+            Color32::WHITE,
+        );
+
+        if let Some(pos) = response.interact_pointer_pos().map(to_image) {
+            if self.tool == Tool::Text {
+                if response.clicked() && !self.text.is_empty() {
+                    self.annotations.push(Annotation::Text {
+                        pos,
+                        text: self.text.clone(),
+                        color: self.color,
+                        size: self.text_size,
+                    });
+                    self.texture = None;
+                }
+            } else if response.drag_started() {
+                self.drag = Some(vec![pos]);
+            } else if response.dragged() {
+                if let Some(points) = &mut self.drag {
+                    if self.tool == Tool::Freehand || points.len() == 1 {
+                        points.push(pos);
+                    } else {
+                        points[1] = pos;
+                    }
+                }
+            }
+        }
+        if response.drag_released() {
+            if let Some(annotation) = self.drag.take().and_then(|points| self.annotation_from(points)) {
+                self.annotations.push(annotation);
+                self.texture = None;
+            }
+        }
+
+        // the annotation being drawn is painted with egui until it is rendered into the preview
+        let stroke = Stroke::new(self.width * scale, self.color);
+        if let Some(points) = self.drag.as_ref().filter(|points| points.len() > 1) {
+            let rect = Rect::from_two_pos(to_screen(points[0]), to_screen(points[1]));
+            match self.tool {
+                Tool::Arrow => {
+                    for (a, b) in arrow_lines(points[0], points[1], self.width) {
+                        painter.line_segment([to_screen(a), to_screen(b)], stroke);
+                    }
+                }
+                Tool::Rect => painter.rect_stroke(rect, 0.0, stroke),
+                Tool::Freehand => {
+                    painter.add(egui::Shape::line(
+                        points.iter().copied().map(to_screen).collect(),
+                        stroke,
+                    ));
+                }
+                Tool::Blur | Tool::Pixelate | Tool::Crop => {
+                    painter.rect_stroke(rect, 0.0, Stroke::new(1.0, Color32::WHITE));
+                }
+                Tool::Text => {}
+            }
+        }
+
+        let crop = self
+            .drag
+            .as_ref()
+            .filter(|points| self.tool == Tool::Crop && points.len() > 1)
+            .map(|points| Rect::from_two_pos(points[0], points[1]))
+            .or_else(|| {
+                self.annotations.iter().rev().find_map(|a| match a {
+                    Annotation::Crop(rect) => Some(*rect),
+                    _ => None,
+                })
+            });
+        if let Some(crop) = crop {
+            let crop = Rect::from_min_max(to_screen(crop.min), to_screen(crop.max));
+            let shade = Color32::from_black_alpha(160);
+            painter.rect_filled(
+                Rect::from_x_y_ranges(canvas.x_range(), canvas.top()..=crop.top()),
+                0.0,
+                shade,
+            );
+            painter.rect_filled(
+                Rect::from_x_y_ranges(canvas.x_range(), crop.bottom()..=canvas.bottom()),
+                0.0,
+                shade,
+            );
+            painter.rect_filled(
+                Rect::from_x_y_ranges(canvas.left()..=crop.left(), crop.y_range()),
+                0.0,
+                shade,
+            );
+            painter.rect_filled(
+                Rect::from_x_y_ranges(crop.right()..=canvas.right(), crop.y_range()),
+                0.0,
+                shade,
+            );
+        }
+    }
+
+    fn annotation_from(&self, points: Vec<Pos2>) -> Option<Annotation> {
+        let (first, last) = (*points.first()?, *points.last()?);
+        if first.distance(last) < 2.0 && self.tool != Tool::Freehand {
+            return None;
+        }
+        let rect = Rect::from_two_pos(first, last);
+        Some(match self.tool {
+            Tool::Arrow => Annotation::Arrow {
+                from: first,
+                to: last,
+                color: self.color,
+                width: self.width,
+            },
+            Tool::Rect => Annotation::Rect {
+                rect,
+                color: self.color,
+                width: self.width,
+            },
+            Tool::Freehand => Annotation::Freehand {
+                points,
+                color: self.color,
+                width: self.width,
+            },
+            Tool::Blur => Annotation::Blur(rect),
+            Tool::Pixelate => Annotation::Pixelate(rect),
+            Tool::Crop => Annotation::Crop(rect),
+            Tool::Text => return None,
+        })
+    }
+}
+
+/// Draws every annotation except crops onto a copy of the image
+fn render(image: &RgbaImage, annotations: &[Annotation]) -> RgbaImage {
+    let mut image = image.clone();
+    for annotation in annotations {
+        match annotation {
+            Annotation::Arrow { from, to, color, width } => {
+                for (a, b) in arrow_lines(*from, *to, *width) {
+                    draw_line(&mut image, a, b, *color, *width);
+                }
+            }
+            Annotation::Rect { rect, color, width } => {
+                let corners = [
+                    rect.left_top(),
+                    rect.right_top(),
+                    rect.right_bottom(),
+                    rect.left_bottom(),
+                ];
+                for (a, b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                    draw_line(&mut image, *a, *b, *color, *width);
+                }
+            }
+            Annotation::Freehand { points, color, width } => {
+                for pair in points.windows(2) {
+                    draw_line(&mut image, pair[0], pair[1], *color, *width);
+                }
+            }
+            Annotation::Text { pos, text, color, size } => draw_text(&mut image, *pos, text, *color, *size),
+            Annotation::Blur(rect) => {
+                if let Some((x, y, w, h)) = pixel_rect(*rect, &image) {
+                    let region = imageops::crop_imm(&image, x, y, w, h).to_image();
+                    imageops::replace(&mut image, &imageops::blur(&region, BLUR_SIGMA), x.into(), y.into());
+                }
+            }
+            Annotation::Pixelate(rect) => {
+                if let Some((x, y, w, h)) = pixel_rect(*rect, &image) {
+                    let region = imageops::crop_imm(&image, x, y, w, h).to_image();
+                    let small = imageops::resize(
+                        &region,
+                        w.div_ceil(PIXELATE_SIZE),
+                        h.div_ceil(PIXELATE_SIZE),
+                        imageops::FilterType::Triangle,
+                    );
+                    let pixelated = imageops::resize(&small, w, h, imageops::FilterType::Nearest);
+                    imageops::replace(&mut image, &pixelated, x.into(), y.into());
+                }
+            }
+            Annotation::Crop(_) => {}
+        }
+    }
+    image
+}
+
+/// Clamps a rect to the image returning `(x, y, width, height)`, `None` when nothing is left
+fn pixel_rect(rect: Rect, image: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let x = rect.min.x.max(0.0) as u32;
+    let y = rect.min.y.max(0.0) as u32;
+    let right = (rect.max.x.max(0.0) as u32).min(image.width());
+    let bottom = (rect.max.y.max(0.0) as u32).min(image.height());
+    (right > x && bottom > y).then_some((x, y, right - x, bottom - y))
+}
+
+/// The shaft and both sides of the arrow head
+fn arrow_lines(from: Pos2, to: Pos2, width: f32) -> [(Pos2, Pos2); 3] {
+    let dir = (to - from).normalized();
+    let length = (width * 4.0).max(12.0);
+    let side = |angle: f32| {
+        let (sin, cos) = angle.sin_cos();
+        to - vec2(dir.x * cos - dir.y * sin, dir.x * sin + dir.y * cos) * length
+    };
+    [(from, to), (to, side(0.5)), (to, side(-0.5))]
+}
+
+/// Draws a line with round caps by stamping circles along it
+fn draw_line(image: &mut RgbaImage, from: Pos2, to: Pos2, color: Color32, width: f32) {
+    let steps = from.distance(to).ceil().max(1.0) as u32;
+    let radius = (width / 2.0).max(0.5);
+    let color = Rgba(color.to_array());
+    for step in 0..=steps {
+        let center = from + (to - from) * (step as f32 / steps as f32);
+        let min_x = (center.x - radius).floor().max(0.0) as u32;
+        let min_y = (center.y - radius).floor().max(0.0) as u32;
+        let max_x = ((center.x + radius).ceil().max(0.0) as u32).min(image.width());
+        let max_y = ((center.y + radius).ceil().max(0.0) as u32).min(image.height());
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                if pos2(x as f32 + 0.5, y as f32 + 0.5).distance(center) <= radius {
+                    image.put_pixel(x, y, color);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! File names for captures
+//!
+//! Templates are strftime formats like `%Y-%m-%d_%H-%M-%S` with a few extra tokens, a `/` in the template creates
+//! subdirectories so captures can be sorted by month or project
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{ascella_config::AscellaConfig, screenshots::focused_window_title, utils::ascella_dir};
+
+pub const DEFAULT_TEMPLATE: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// Tokens besides the strftime ones with what they are replaced with, shown in the settings
+pub const TOKENS: [(&str, &str); 5] = [
+    ("{counter}", "a number that goes up with every capture"),
+    ("{random}", "8 random letters and digits"),
+    ("{mode}", "area, window, screen, recording or clipboard"),
+    ("{window}", "title of the focused window"),
+    ("{profile}", "name of the uploader profile"),
+];
+
+/// Renders the template into a file name without extension
+pub async fn render(template: &str, mode: &str, profile: &str) -> Result<String> {
+    let mut name = String::new();
+    // chrono only reports invalid specifiers when formatting
+    write!(name, "{}", chrono::offset::Local::now().format(template))
+        .map_err(|_| anyhow!("Invalid filename template {template}"))?;
+
+    if name.contains("{counter}") {
+        name = name.replace("{counter}", &next_counter()?.to_string());
+    }
+    if name.contains("{random}") {
+        let random: String = std::iter::repeat_with(fastrand::alphanumeric).take(8).collect();
+        name = name.replace("{random}", &random);
+    }
+    if name.contains("{window}") {
+        let title = focused_window_title().await.unwrap_or_else(|| "unknown".to_string());
+        name = name.replace("{window}", &sanitize(&title));
+    }
+    name = name.replace("{mode}", mode).replace("{profile}", &sanitize(profile));
+
+    if name.split('/').all(|part| part.trim().is_empty()) {
+        return Err(anyhow!("Filename template {template} renders to an empty name"));
+    }
+    Ok(name)
+}
+
+/// A path in `dir` for the template that no file uses yet, directories the template asks for are created
+pub async fn unique_path(dir: &Path, template: &str, mode: &str, profile: &str, extension: &str) -> Result<PathBuf> {
+    let path = free_path(dir, &render(template, mode, profile).await?, extension);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    Ok(path)
+}
+
+/// `dir/name.extension`, with a number added to the name when that file already exists
+pub fn free_path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+    let file = |suffix: String| match extension {
+        "" => dir.join(format!("{name}{suffix}")),
+        extension => dir.join(format!("{name}{suffix}.{extension}")),
+    };
+    let mut path = file(String::new());
+    let mut counter = 1;
+    while path.exists() {
+        path = file(format!("-{counter}"));
+        counter += 1;
+    }
+    path
+}
+
+/// Where a new capture is saved, using the output directory and filename template from the config
+pub async fn capture_path(config: &AscellaConfig, mode: &str, extension: &str) -> Result<PathBuf> {
+    unique_path(
+        &config.output_dir(),
+        &config.filename_template,
+        mode,
+        &config.profile().name,
+        extension,
+    )
+    .await
+}
+
+/// Window titles and profile names can contain anything, keep them to a single path component
+fn sanitize(value: &str) -> String {
+    value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(100)
+        .collect()
+}
+
+/// Bumps the counter stored in `~/.ascella/counter` and returns the new value
+fn next_counter() -> Result<u64> {
+    let file = ascella_dir().join("counter");
+    let counter = fs::read_to_string(&file)
+        .ok()
+        .and_then(|counter| counter.trim().parse::<u64>().ok())
+        .unwrap_or(0)
+        + 1;
+    fs::write(&file, counter.to_string())?;
+    Ok(counter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ascella-filename-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn render_tokens() {
+        let year = chrono::offset::Local::now().format("%Y").to_string();
+        assert_eq!(
+            render("%Y_{mode}", "area", "work").await.unwrap(),
+            format!("{year}_area")
+        );
+        assert_eq!(render("{profile}/x", "area", "a/b: c").await.unwrap(), "a_b_ c/x");
+        let random = render("{random}", "area", "work").await.unwrap();
+        assert!(random.len() == 8 && random.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[tokio::test]
+    async fn render_rejects_bad_templates() {
+        assert!(render("%Q", "area", "work").await.is_err());
+        assert!(render("", "area", "work").await.is_err());
+        assert!(render(" / ", "area", "work").await.is_err());
+    }
+
+    #[test]
+    fn free_path_counts_up() {
+        let dir = temp_dir("free");
+        assert_eq!(free_path(&dir, "a", "png"), dir.join("a.png"));
+        fs::write(dir.join("a.png"), "").unwrap();
+        fs::write(dir.join("a-1.png"), "").unwrap();
+        assert_eq!(free_path(&dir, "a", "png"), dir.join("a-2.png"));
+        assert_eq!(free_path(&dir, "a", ""), dir.join("a"));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn unique_path_creates_directories() {
+        let dir = temp_dir("unique");
+        let path = unique_path(&dir, "{mode}/shot", "window", "work", "jpg").await.unwrap();
+        assert_eq!(path, dir.join("window").join("shot.jpg"));
+        assert!(dir.join("window").is_dir());
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,614 @@
+//! Global hotkeys while the gui is running
+//!
+//! On X11 the keys are grabbed on the root window with XGrabKey. Wayland clients can't grab keys, so there the
+//! GlobalShortcuts portal is asked to bind them and the compositor may let the user confirm or pick other keys
+
+use std::{fmt, thread::JoinHandle, time::Duration};
+
+use anyhow::{anyhow, Result};
+use egui_notify::Toast;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{ascella_config::AscellaConfig, Request, RequestResponse, SendScreenshot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    Area,
+    Window,
+    Screen,
+    Clipboard,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 4] = [Self::Area, Self::Window, Self::Screen, Self::Clipboard];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Area => "area",
+            Self::Window => "window",
+            Self::Screen => "screen",
+            Self::Clipboard => "clipboard",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Area => "Screenshot an area",
+            Self::Window => "Screenshot the focused window",
+            Self::Screen => "Screenshot the screen",
+            Self::Clipboard => "Upload the clipboard",
+        }
+    }
+
+    fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.id() == id)
+    }
+
+    /// The request doing what the hotkey is bound to with `config`
+    pub fn request(&self, config: &AscellaConfig) -> Request {
+        let send = match self {
+            Self::Area => SendScreenshot::Area,
+            Self::Window => SendScreenshot::Window,
+            Self::Screen => SendScreenshot::Screen,
+            Self::Clipboard => return Request::UploadClipboard(config.clone()),
+        };
+        Request::Screenshot {
+            r_type: config.s_type.clone(),
+            send,
+            config: config.clone(),
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    pub enabled: bool,
+    /// keys like `Ctrl+Alt+A`, empty leaves the action unbound
+    pub area: String,
+    pub window: String,
+    pub screen: String,
+    pub clipboard: String,
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            area: "Ctrl+Alt+A".to_string(),
+            window: "Ctrl+Alt+W".to_string(),
+            screen: "Ctrl+Alt+S".to_string(),
+            clipboard: "Ctrl+Alt+U".to_string(),
+        }
+    }
+}
+
+impl HotkeyConfig {
+    pub fn binding_mut(&mut self, action: HotkeyAction) -> &mut String {
+        match action {
+            HotkeyAction::Area => &mut self.area,
+            HotkeyAction::Window => &mut self.window,
+            HotkeyAction::Screen => &mut self.screen,
+            HotkeyAction::Clipboard => &mut self.clipboard,
+        }
+    }
+
+    /// Bound actions with their keys
+    fn hotkeys(&self) -> Result<Vec<(HotkeyAction, Hotkey)>> {
+        let mut hotkeys: Vec<(HotkeyAction, Hotkey)> = Vec::new();
+        for (action, binding) in [
+            (HotkeyAction::Area, &self.area),
+            (HotkeyAction::Window, &self.window),
+            (HotkeyAction::Screen, &self.screen),
+            (HotkeyAction::Clipboard, &self.clipboard),
+        ] {
+            if binding.trim().is_empty() {
+                continue;
+            }
+            let hotkey = Hotkey::parse(binding).map_err(|e| anyhow!("Invalid hotkey for {}, {e}", action.id()))?;
+            if let Some((other, _)) = hotkeys.iter().find(|(_, bound)| *bound == hotkey) {
+                return Err(anyhow!("{hotkey} is bound to both {} and {}", other.id(), action.id()));
+            }
+            hotkeys.push((action, hotkey));
+        }
+        Ok(hotkeys)
+    }
+}
+
+/// Keys besides letters and digits, with their xkb keysym name and value
+const KEYS: [(&str, &str, u32); 11] = [
+    ("Print", "Print", 0xff61),
+    ("Space", "space", 0x20),
+    ("Escape", "Escape", 0xff1b),
+    ("Pause", "Pause", 0xff13),
+    ("Insert", "Insert", 0xff63),
+    ("Delete", "Delete", 0xffff),
+    ("Home", "Home", 0xff50),
+    ("End", "End", 0xff57),
+    ("PageUp", "Page_Up", 0xff55),
+    ("PageDown", "Page_Down", 0xff56),
+    ("Tab", "Tab", 0xff09),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hotkey {
+    pub modifiers: Modifiers,
+    /// name as shown in the settings
+    pub key: String,
+    /// xkb keysym name, used for portal triggers
+    pub keysym_name: String,
+    pub keysym: u32,
+}
+
+impl Hotkey {
+    /// Parses keys like `Ctrl+Shift+A`, `Super+Print` or `F5`
+    pub fn parse(binding: &str) -> Result<Self> {
+        let parts: Vec<&str> = binding.split('+').map(str::trim).collect();
+        let (key, modifier_names) = parts.split_last().ok_or_else(|| anyhow!("no key"))?;
+        let mut modifiers = Modifiers::default();
+        for modifier in modifier_names {
+            let flag = match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => &mut modifiers.ctrl,
+                "alt" => &mut modifiers.alt,
+                "shift" => &mut modifiers.shift,
+                "super" | "logo" | "win" | "meta" => &mut modifiers.logo,
+                _ => return Err(anyhow!("unknown modifier {modifier}")),
+            };
+            *flag = true;
+        }
+
+        let (key, keysym_name, keysym) = match key.chars().collect::<Vec<_>>()[..] {
+            [c] if c.is_ascii_alphanumeric() => {
+                let c = c.to_ascii_lowercase();
+                (c.to_ascii_uppercase().to_string(), c.to_string(), c as u32)
+            }
+            ['F' | 'f', ..] if key[1..].parse::<u32>().is_ok_and(|n| (1..=24).contains(&n)) => {
+                let n: u32 = key[1..].parse()?;
+                (format!("F{n}"), format!("F{n}"), 0xffbe + n - 1)
+            }
+            _ => {
+                let (name, keysym_name, keysym) = KEYS
+                    .into_iter()
+                    .find(|(name, ..)| name.eq_ignore_ascii_case(key))
+                    .ok_or_else(|| anyhow!("unknown key {key}"))?;
+                (name.to_string(), keysym_name.to_string(), keysym)
+            }
+        };
+        Ok(Self {
+            modifiers,
+            key,
+            keysym_name,
+            keysym,
+        })
+    }
+
+    /// The key in the format of the XDG shortcuts spec, like `CTRL+ALT+a`
+    fn portal_trigger(&self) -> String {
+        let mut trigger = String::new();
+        for (enabled, name) in [
+            (self.modifiers.ctrl, "CTRL+"),
+            (self.modifiers.alt, "ALT+"),
+            (self.modifiers.shift, "SHIFT+"),
+            (self.modifiers.logo, "LOGO+"),
+        ] {
+            if enabled {
+                trigger.push_str(name);
+            }
+        }
+        trigger.push_str(&self.keysym_name);
+        trigger
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (enabled, name) in [
+            (self.modifiers.ctrl, "Ctrl+"),
+            (self.modifiers.alt, "Alt+"),
+            (self.modifiers.shift, "Shift+"),
+            (self.modifiers.logo, "Super+"),
+        ] {
+            if enabled {
+                f.write_str(name)?;
+            }
+        }
+        f.write_str(&self.key)
+    }
+}
+
+/// The running listener, the hotkeys are released when it is dropped
+pub struct HotkeyListener {
+    cancel: CancellationToken,
+    /// the X11 listener thread, joined by `stop` so its grabs are gone before new ones are made
+    thread: Option<JoinHandle<()>>,
+    /// wakes the X11 thread up from waiting for key presses so it sees the cancellation
+    #[cfg(target_os = "linux")]
+    waker: Option<x11::Waker>,
+}
+
+impl HotkeyListener {
+    fn cancel(&self) {
+        self.cancel.cancel();
+        #[cfg(target_os = "linux")]
+        if let Some(waker) = &self.waker {
+            waker.wake();
+        }
+    }
+
+    /// Releases the hotkeys and waits until they are, the thread is joined off the async threads
+    pub async fn stop(mut self) {
+        self.cancel();
+        if let Some(thread) = self.thread.take() {
+            tokio::task::spawn_blocking(move || thread.join()).await.ok();
+        }
+    }
+}
+
+impl Drop for HotkeyListener {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Registers the hotkeys of the config, pressing one sends `Request::Hotkey` to the worker
+pub fn start(
+    config: &HotkeyConfig,
+    requests: UnboundedSender<Request>,
+    sender: UnboundedSender<RequestResponse>,
+) -> Option<HotkeyListener> {
+    if !config.enabled {
+        return None;
+    }
+    let hotkeys = match config.hotkeys() {
+        Ok(hotkeys) if hotkeys.is_empty() => return None,
+        Ok(hotkeys) => hotkeys,
+        Err(e) => {
+            sender.send(RequestResponse::Toast(Toast::error(e.to_string()))).ok();
+            return None;
+        }
+    };
+    let cancel = CancellationToken::new();
+    let on_press = move |action: HotkeyAction| {
+        info!("Hotkey for {} pressed", action.id());
+        requests.send(Request::Hotkey(action)).ok();
+    };
+    let on_error = move |e: anyhow::Error| {
+        tracing::error!("Hotkeys stopped {e:?}");
+        sender
+            .send(RequestResponse::Toast(Toast::error(format!(
+                "Failed registering hotkeys\n{e}"
+            ))))
+            .ok();
+    };
+
+    #[cfg(target_os = "linux")]
+    {
+        let listener_cancel = cancel.clone();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            tokio::spawn(async move {
+                if let Err(e) = portal::listen(hotkeys, listener_cancel, on_press).await {
+                    on_error(e);
+                }
+            });
+            return Some(HotkeyListener {
+                cancel,
+                thread: None,
+                waker: None,
+            });
+        }
+        let grabs = match x11::Grabs::new(hotkeys) {
+            Ok(grabs) => grabs,
+            Err(e) => {
+                on_error(e);
+                return None;
+            }
+        };
+        let waker = Some(grabs.waker());
+        let thread = std::thread::Builder::new()
+            .name("ascella-hotkeys".to_owned())
+            .spawn(move || {
+                if let Err(e) = grabs.listen(listener_cancel, on_press) {
+                    on_error(e);
+                }
+            })
+            .ok();
+        Some(HotkeyListener { cancel, thread, waker })
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (hotkeys, cancel, on_press);
+        on_error(anyhow!("Global hotkeys are only supported on Linux"));
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use std::sync::Arc;
+
+    use anyhow::{anyhow, Result};
+    use tokio_util::sync::CancellationToken;
+    use tracing::info;
+    use x11rb::{
+        connection::Connection,
+        protocol::{
+            xproto::{
+                AtomEnum, ClientMessageEvent, ConnectionExt, CreateWindowAux, EventMask, GrabMode, ModMask, Window,
+                WindowClass,
+            },
+            Event,
+        },
+        rust_connection::RustConnection,
+    };
+
+    use super::{Hotkey, HotkeyAction};
+
+    fn modifier_mask(hotkey: &Hotkey) -> u16 {
+        let mut mask = 0;
+        for (enabled, modifier) in [
+            (hotkey.modifiers.ctrl, ModMask::CONTROL),
+            (hotkey.modifiers.alt, ModMask::M1),
+            (hotkey.modifiers.shift, ModMask::SHIFT),
+            (hotkey.modifiers.logo, ModMask::M4),
+        ] {
+            if enabled {
+                mask |= u16::from(modifier);
+            }
+        }
+        mask
+    }
+
+    /// Sends a message to the hidden window of the listener so it stops waiting for events
+    pub struct Waker {
+        conn: Arc<RustConnection>,
+        window: Window,
+    }
+
+    impl Waker {
+        pub fn wake(&self) {
+            let event = ClientMessageEvent::new(32, self.window, AtomEnum::NONE, [0u32; 5]);
+            // an empty event mask sends the event to the client that created the window, which is us
+            if self
+                .conn
+                .send_event(false, self.window, EventMask::NO_EVENT, event)
+                .is_ok()
+            {
+                self.conn.flush().ok();
+            }
+        }
+    }
+
+    /// Keys grabbed on the root window, closing the connection releases the grabs
+    pub struct Grabs {
+        conn: Arc<RustConnection>,
+        window: Window,
+        /// keycode, modifiers and what they do
+        grabbed: Vec<(u8, u16, HotkeyAction)>,
+    }
+
+    impl Grabs {
+        pub fn new(hotkeys: Vec<(HotkeyAction, Hotkey)>) -> Result<Self> {
+            let (conn, screen) = x11rb::connect(None).map_err(|e| anyhow!("Failed connecting to the X server {e}"))?;
+            let setup = conn.setup();
+            let root = setup.roots[screen].root;
+            let (min_keycode, max_keycode) = (setup.min_keycode, setup.max_keycode);
+            let mapping = conn
+                .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)?
+                .reply()?;
+            // caps lock and num lock count as modifiers, grab every combination of them so they don't break the hotkeys
+            let (caps_lock, num_lock) = (u16::from(ModMask::LOCK), u16::from(ModMask::M2));
+
+            let mut grabbed = Vec::new();
+            for (action, hotkey) in hotkeys {
+                let keycode = mapping
+                    .keysyms
+                    .chunks(mapping.keysyms_per_keycode.max(1) as usize)
+                    .position(|keysyms| keysyms.contains(&hotkey.keysym))
+                    .map(|i| min_keycode + i as u8)
+                    .ok_or_else(|| anyhow!("{hotkey} is not on this keyboard"))?;
+                let modifiers = modifier_mask(&hotkey);
+                for locks in [0, caps_lock, num_lock, caps_lock | num_lock] {
+                    conn.grab_key(
+                        true,
+                        root,
+                        ModMask::from(modifiers | locks),
+                        keycode,
+                        GrabMode::ASYNC,
+                        GrabMode::ASYNC,
+                    )?
+                    .check()
+                    .map_err(|_| anyhow!("{hotkey} is already used by another program"))?;
+                }
+                info!("Grabbed {hotkey} for {}", action.id());
+                grabbed.push((keycode, modifiers, action));
+            }
+            // never mapped, it only receives the message that wakes the listener up
+            let window = conn.generate_id()?;
+            conn.create_window(
+                0,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_ONLY,
+                0,
+                &CreateWindowAux::new(),
+            )?
+            .check()?;
+            conn.flush()?;
+            Ok(Self {
+                conn: Arc::new(conn),
+                window,
+                grabbed,
+            })
+        }
+
+        pub fn waker(&self) -> Waker {
+            Waker {
+                conn: self.conn.clone(),
+                window: self.window,
+            }
+        }
+
+        /// Calls `on_press` for the grabbed keys until cancelled, blocks while waiting for events
+        pub fn listen(self, cancel: CancellationToken, on_press: impl Fn(HotkeyAction)) -> Result<()> {
+            // caps lock and num lock are left out when comparing modifiers
+            let locks = u16::from(ModMask::LOCK) | u16::from(ModMask::M2);
+            while !cancel.is_cancelled() {
+                let event = match self.conn.wait_for_event() {
+                    Ok(event) => event,
+                    Err(_) if cancel.is_cancelled() => break,
+                    Err(e) => return Err(anyhow!("Lost the connection to the X server {e}")),
+                };
+                // anything else is the waker or an event we don't care about, the loop checks the cancellation
+                if let Event::KeyPress(event) = event {
+                    // only the modifier bits, without the locks and mouse buttons
+                    let state = u16::from(event.state) & 0xff & !locks;
+                    if let Some((.., action)) = self
+                        .grabbed
+                        .iter()
+                        .find(|(keycode, modifiers, _)| *keycode == event.detail && *modifiers == state)
+                    {
+                        on_press(*action);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod portal {
+    use anyhow::{anyhow, Result};
+    use ashpd::{
+        desktop::global_shortcuts::{GlobalShortcuts, NewShortcut},
+        WindowIdentifier,
+    };
+    use futures_util::StreamExt;
+    use tokio_util::sync::CancellationToken;
+    use tracing::info;
+
+    use super::{Hotkey, HotkeyAction};
+
+    /// Binds the keys through the portal and calls `on_press` for them until cancelled
+    pub async fn listen(
+        hotkeys: Vec<(HotkeyAction, Hotkey)>,
+        cancel: CancellationToken,
+        on_press: impl Fn(HotkeyAction),
+    ) -> Result<()> {
+        let portal = GlobalShortcuts::new()
+            .await
+            .map_err(|e| anyhow!("The GlobalShortcuts portal is not available, bind `ascella area` and friends in your compositor instead\n{e}"))?;
+        let session = portal.create_session().await?;
+        let shortcuts: Vec<NewShortcut> = hotkeys
+            .iter()
+            .map(|(action, hotkey)| {
+                NewShortcut::new(action.id(), action.description()).preferred_trigger(hotkey.portal_trigger().as_str())
+            })
+            .collect();
+        let bound = portal
+            .bind_shortcuts(&session, &shortcuts, &WindowIdentifier::default())
+            .await?
+            .response()?;
+        for shortcut in bound.shortcuts() {
+            info!("Bound {} to {}", shortcut.id(), shortcut.trigger_description());
+        }
+
+        let activated = portal.receive_activated().await?;
+        futures_util::pin_mut!(activated);
+        loop {
+            tokio::select! {
+                activation = activated.next() => {
+                    let Some(activation) = activation else { break };
+                    if let Some(action) = HotkeyAction::from_id(activation.shortcut_id()) {
+                        on_press(action);
+                    }
+                }
+                _ = cancel.cancelled() => break,
+            }
+        }
+        session.close().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_round_trips() {
+        for binding in ["Ctrl+Alt+A", "Shift+Super+Print", "Ctrl+F12", "7", "Alt+PageDown"] {
+            assert_eq!(Hotkey::parse(binding).unwrap().to_string(), binding);
+        }
+        let hotkey = Hotkey::parse(" control + win + space ").unwrap();
+        assert_eq!(hotkey.to_string(), "Ctrl+Super+Space");
+        assert_eq!(Hotkey::parse(&hotkey.to_string()).unwrap(), hotkey);
+    }
+
+    #[test]
+    fn function_keys() {
+        assert_eq!(Hotkey::parse("F1").unwrap().keysym, 0xffbe);
+        assert_eq!(Hotkey::parse("f24").unwrap().keysym, 0xffbe + 23);
+        assert!(Hotkey::parse("F0").is_err());
+        assert!(Hotkey::parse("F25").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_and_modifiers() {
+        assert!(Hotkey::parse("Ctrl+Banana").is_err());
+        assert!(Hotkey::parse("Hyper+A").is_err());
+        assert!(Hotkey::parse("Ctrl+").is_err());
+        assert!(Hotkey::parse("").is_err());
+    }
+
+    #[test]
+    fn portal_triggers() {
+        assert_eq!(Hotkey::parse("Ctrl+Alt+A").unwrap().portal_trigger(), "CTRL+ALT+a");
+        assert_eq!(
+            Hotkey::parse("Super+Shift+PageUp").unwrap().portal_trigger(),
+            "SHIFT+LOGO+Page_Up"
+        );
+    }
+
+    #[test]
+    fn duplicate_bindings() {
+        let config = HotkeyConfig {
+            window: "ctrl+alt+a".to_string(),
+            ..Default::default()
+        };
+        let err = config.hotkeys().unwrap_err().to_string();
+        assert!(err.contains("area") && err.contains("window"), "{err}");
+
+        let config = HotkeyConfig {
+            screen: String::new(),
+            ..Default::default()
+        };
+        let actions: Vec<HotkeyAction> = config
+            .hotkeys()
+            .unwrap()
+            .into_iter()
+            .map(|(action, _)| action)
+            .collect();
+        assert_eq!(
+            actions,
+            [HotkeyAction::Area, HotkeyAction::Window, HotkeyAction::Clipboard]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Saving captures without uploading them
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    ascella_config::AscellaConfig,
+    clipboard, filename,
+    upload_history::{self, HistoryEntry},
+    utils::infer_mime,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LocalSaveConfig {
+    /// put the image itself on the clipboard instead of a url
+    pub copy_image: bool,
+}
+
+impl Default for LocalSaveConfig {
+    fn default() -> Self {
+        Self { copy_image: true }
+    }
+}
+
+/// Keeps a capture instead of uploading it and records it in the local history, returns where it was saved
+///
+/// Captures are already named with the filename template in the output directory, other files are moved there
+pub async fn save(path: &Path, config: &AscellaConfig) -> Result<PathBuf> {
+    let local = &config.local_save;
+    let dir = config.output_dir();
+    if path.starts_with(&dir) {
+        return record(path.to_path_buf(), local).await;
+    }
+    tokio::fs::create_dir_all(&dir).await?;
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    let target = filename::free_path(&dir, &name, &extension);
+    // rename fails across file systems
+    if tokio::fs::rename(path, &target).await.is_err() {
+        tokio::fs::copy(path, &target).await?;
+        tokio::fs::remove_file(path).await?;
+    }
+    record(target, local).await
+}
+
+async fn record(target: PathBuf, local: &LocalSaveConfig) -> Result<PathBuf> {
+    info!("Saved capture to {}", target.display());
+
+    let mime = infer_mime(&target)?;
+    if local.copy_image && mime.starts_with("image/") {
+        if let Err(e) = clipboard::copy_image(&target, &mime).await {
+            tracing::error!("Failed copying the image {e:?}");
+        }
+    }
+
+    let entry = HistoryEntry {
+        id: upload_history::next_id(),
+        url: format!("file://{}", target.display()),
+        path: target.clone(),
+        timestamp: chrono::Utc::now().timestamp(),
+        size: tokio::fs::metadata(&target).await?.len(),
+        mime,
+        uploader: "local".to_string(),
+        ..Default::default()
+    };
+    if let Err(e) = upload_history::append(&entry) {
+        tracing::error!("Failed saving capture to history {e:?}");
+    }
+    Ok(target)
+}
@@ -1,6 +1,12 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-use std::{collections::HashMap, env, fs, process, thread, time::Duration};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Result};
 use ascella_config::AscellaConfig;
@@ -12,8 +18,13 @@ use eframe::egui::{self, Color32};
 
 use egui_notify::Toast;
 use egui_tracing::EventCollector;
-use request_handler::handle_event;
+use hotkeys::HotkeyAction;
+use pipeline::StageStatus;
+use recording::Recording;
+use redaction::{RedactionConfig, Region};
+use request_handler::{handle_event, print_progress, Countdown, UploadProgress, WorkerState};
 use reqwest::StatusCode;
+use retention::CleanupReport;
 use screenshots::ScreenshotType;
 use serde::Deserialize;
 
@@ -25,19 +36,35 @@ use tracing_subscriber::{
     util::SubscriberInitExt,
     Layer,
 };
-use utils::ascella_dir;
+use upload_history::HistoryEntry;
+use upload_queue::{UploadJob, UploadQueue};
+use utils::{ascella_dir, format_bytes};
 use webserver::start_server;
 
 mod ascella_config;
 mod cli;
 mod clipboard;
 mod easy_mark;
+mod editor;
+mod filename;
+mod hotkeys;
+mod local_save;
+mod metadata;
+mod pipeline;
+mod recording;
+mod redaction;
 mod request_handler;
+mod retention;
 mod screens;
 mod screenshots;
+mod sxcu;
+mod text;
 mod theme;
 mod ui;
+mod upload_history;
+mod upload_queue;
 mod utils;
+mod watermark;
 mod webserver;
 pub enum RequestResponse {
     Request {
@@ -47,6 +74,32 @@ pub enum RequestResponse {
     },
     Toast(Toast),
     UpdateConfigFromStringSxcu(Vec<u8>),
+    HistoryUpdated,
+    QueueUpdated(Vec<UploadJob>),
+    /// Progress of the queued upload with the id
+    UploadProgress {
+        id: u64,
+        progress: UploadProgress,
+    },
+    /// The queued upload with the id stopped sending, it may still be retried
+    UploadFinished(u64),
+    /// The pipeline stage a screenshot is in, `None` once it is done
+    PipelineStage(Option<StageStatus>),
+    /// When the running recording started, `None` once it stopped
+    Recording(Option<Instant>),
+    CleanupReport(CleanupReport),
+    /// The delayed capture waiting to be taken, `None` once it was
+    Countdown(Option<Countdown>),
+    RedactionPreview {
+        path: PathBuf,
+        regions: Vec<Region>,
+    },
+    /// A screenshot waiting to be annotated before it gets uploaded
+    Annotate {
+        path: PathBuf,
+        /// boxed, the config is a lot larger than the other responses
+        config: Box<AscellaConfig>,
+    },
 }
 
 pub enum SendScreenshot {
@@ -55,6 +108,16 @@ pub enum SendScreenshot {
     Window,
 }
 
+impl SendScreenshot {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Area => "area",
+            Self::Screen => "screen",
+            Self::Window => "window",
+        }
+    }
+}
+
 #[allow(clippy::enum_variant_names)]
 pub enum Request {
     DoRequest {
@@ -65,7 +128,50 @@ pub enum Request {
         r_type: ScreenshotType,
         send: SendScreenshot,
         config: AscellaConfig,
-        print: bool,
+        /// counted down on its own task before the screenshot is taken
+        delay: Duration,
+    },
+    /// Process a screenshot with the pipeline and queue it for upload
+    UploadCapture {
+        path: PathBuf,
+        config: AscellaConfig,
+    },
+    /// Queue existing files for upload
+    Upload {
+        paths: Vec<PathBuf>,
+        config: AscellaConfig,
+    },
+    /// Upload whatever is in the clipboard
+    UploadClipboard(AscellaConfig),
+    RetryUpload {
+        id: u64,
+        config: AscellaConfig,
+    },
+    CancelUpload(u64),
+    /// An upload started by the queue finished, with the url on success
+    UploadFinished {
+        id: u64,
+        result: Result<String>,
+    },
+    DeleteUpload(HistoryEntry),
+    /// Find what would be redacted in a file without changing it
+    PreviewRedaction {
+        path: PathBuf,
+        config: RedactionConfig,
+    },
+    /// Start recording the screen, or an area selected first
+    StartRecording {
+        area: bool,
+        config: AscellaConfig,
+    },
+    /// Stop the recording and queue it for upload
+    StopRecording,
+    /// A global hotkey was pressed, it is run with the last saved config
+    Hotkey(HotkeyAction),
+    /// Apply the retention policy, or only report what it would remove
+    Cleanup {
+        config: AscellaConfig,
+        dry_run: bool,
     },
     SaveConfig(AscellaConfig),
 }
@@ -92,10 +198,11 @@ where
 }
 
 #[derive(Deserialize)]
-#[allow(dead_code)]
 pub struct UploadResponse {
     url: String,
+    #[serde(default)]
     delete: String,
+    #[serde(default)]
     metadata: String,
 }
 
@@ -107,32 +214,102 @@ fn create_rt() -> Result<Runtime> {
         .build()?)
 }
 
+async fn record_command(
+    area: bool,
+    duration: Option<u64>,
+    config: &AscellaConfig,
+    client: &reqwest::Client,
+) -> Result<()> {
+    let path = filename::capture_path(config, "recording", "mp4").await?;
+    let recording = Recording::start(&config.recording, area, path).await?;
+    match duration {
+        Some(seconds) => {
+            eprintln!("Recording for {seconds}s, press ctrl+c to stop early");
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(seconds)) => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        None => {
+            eprintln!("Recording, press ctrl+c to stop");
+            tokio::signal::ctrl_c().await?;
+        }
+    }
+    eprintln!("Stopped after {}s", recording.started.elapsed().as_secs());
+    let path = recording.stop().await?;
+    if config.is_local_only() {
+        println!("{}", local_save::save(&path, config).await?.display());
+    } else {
+        request_handler::upload_file(path, config, client, true, Some(print_progress())).await?;
+    }
+    Ok(())
+}
+
+async fn delete_command(target: &str, yes: bool, config: &AscellaConfig, client: &reqwest::Client) -> Result<()> {
+    let entry = upload_history::find(target)?.ok_or_else(|| anyhow!("{target} not found in the upload history"))?;
+    if !yes {
+        print!("Delete {}? [y/N] ", entry.url);
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return Err(anyhow!("Aborted"));
+        }
+    }
+    request_handler::delete_upload(&entry, config, client).await?;
+    println!("Deleted {}", entry.url);
+    Ok(())
+}
+
+fn cleanup_command(dry_run: bool, config: &AscellaConfig) -> Result<()> {
+    let protected: Vec<PathBuf> = UploadQueue::load().jobs().iter().map(|job| job.path.clone()).collect();
+    let report = retention::cleanup(config, &protected, dry_run)?;
+    for removal in &report.removed {
+        println!(
+            "{} {} ({}, {})",
+            if dry_run { "Would remove" } else { "Removed" },
+            removal.path.display(),
+            format_bytes(removal.size),
+            removal.reason
+        );
+    }
+    println!(
+        "{} {} captures, {} freed, {} kept",
+        if dry_run { "Would remove" } else { "Removed" },
+        report.removed.len(),
+        format_bytes(report.freed()),
+        report.kept
+    );
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let arg = AscellaCli::parse();
 
-    let config: AscellaConfig = Config::builder()
+    let mut config: AscellaConfig = Config::builder()
         .add_source(File::new("ascella.toml", FileFormat::Toml).required(false))
         .add_source(File::new("ascella.json", FileFormat::Json5).required(false))
         .add_source(File::new(ascella_dir().join("ascella.toml").to_str().unwrap(), FileFormat::Toml).required(false))
         .add_source(File::new(ascella_dir().join("ascella.json").to_str().unwrap(), FileFormat::Json5).required(false))
         .add_source(Environment::default())
-        .set_default("api_url", "https://api.picup.click/api/v3")?
-        .set_default("request_url", "https://api.picup.click/api/v3/upload")?
-        .set_default("api_key", "")?
         .set_default("debug", false)?
-        .set_default("headers", HashMap::<String, String>::default())?
         .set_default("webserver", true)?
         .set_default("theme", 2)?
-        .set_default("optimize_png", false)?
-        .set_default("optimize_timeout", 100)?
         .set_default("console_logging", false)?
         .set_default("notifications_enabled", true)?
+        .set_default("strip_metadata", true)?
+        .set_default("local_only", false)?
+        .set_default("output_dir", "")?
+        .set_default("filename_template", filename::DEFAULT_TEMPLATE)?
         .set_default(
             "s_type",
-            toml::from_str::<config::Value>(&toml::to_string(&ScreenshotType::Flameshot)?)?,
+            toml::from_str::<config::Value>(&toml::to_string(
+                &ScreenshotType::detect().into_iter().next().unwrap_or_default(),
+            )?)?,
         )?
         .build()?
         .try_deserialize()?;
+    config.migrate();
 
     let client = reqwest::Client::builder()
         .user_agent(format!(
@@ -145,16 +322,64 @@ fn main() -> Result<()> {
 
     // subcommand branch
     if let Some(sub) = arg.command {
+        // the overrides are for this one command, the gui would save them into the config
+        let mut config = config.clone();
+        if arg.keep_metadata {
+            config.strip_metadata = false;
+        }
+        if arg.no_upload {
+            config.local_only = true;
+        }
+        if let Some(profile) = &arg.profile {
+            config.select_profile(profile)?;
+        }
         create_rt()?.block_on(async {
-            let (sender, _) = tokio::sync::mpsc::unbounded_channel::<RequestResponse>();
             let (delay, send) = match sub {
                 Commands::Area { delay } => (delay, SendScreenshot::Area),
                 Commands::Window { delay } => (delay, SendScreenshot::Window),
                 Commands::Screen { delay } => (delay, SendScreenshot::Screen),
-                Commands::Upload { file } => match request_handler::upload_file(file, &config, &client, true).await {
-                    Ok(_) => {
-                        process::exit(0);
+                Commands::Upload { file } => {
+                    match request_handler::upload_file(file, &config, &client, true, Some(print_progress())).await {
+                        Ok(_) => {
+                            process::exit(0);
+                        }
+                        Err(e) => {
+                            println!("{}", e);
+                            process::exit(1);
+                        }
+                    }
+                }
+                Commands::Clipboard => {
+                    let result = match clipboard::paste_to_file(&config).await {
+                        Ok(path) => {
+                            request_handler::upload_file(path, &config, &client, true, Some(print_progress())).await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(_) => process::exit(0),
+                        Err(e) => {
+                            println!("{}", e);
+                            process::exit(1);
+                        }
+                    }
+                }
+                Commands::Record { area, duration } => match record_command(area, duration, &config, &client).await {
+                    Ok(()) => process::exit(0),
+                    Err(e) => {
+                        println!("{}", e);
+                        process::exit(1);
                     }
+                },
+                Commands::Delete { target, yes } => match delete_command(&target, yes, &config, &client).await {
+                    Ok(()) => process::exit(0),
+                    Err(e) => {
+                        println!("{}", e);
+                        process::exit(1);
+                    }
+                },
+                Commands::Cleanup { dry_run } => match cleanup_command(dry_run, &config) {
+                    Ok(()) => process::exit(0),
                     Err(e) => {
                         println!("{}", e);
                         process::exit(1);
@@ -163,17 +388,27 @@ fn main() -> Result<()> {
             };
 
             if let Some(delay) = delay {
-                std::thread::sleep(Duration::from_millis(delay))
+                tokio::time::sleep(Duration::from_millis(delay)).await;
             }
-            let data = Request::Screenshot {
-                r_type: config.s_type.clone(),
-                print: true,
-                send,
-                config,
-            };
-            if let Err(e) = handle_event(data, &client, &sender).await {
-                tracing::error!("{e:?}");
+            let result = match request_handler::capture(&config.s_type, send, &config).await {
+                Ok(path) => {
+                    let path = pipeline::run(path, &config, None).await;
+                    if config.is_local_only() {
+                        local_save::save(&path, &config)
+                            .await
+                            .map(|saved| println!("{}", saved.display()))
+                    } else {
+                        request_handler::upload_file(path, &config, &client, true, Some(print_progress()))
+                            .await
+                            .map(|_| ())
+                    }
+                }
+                Err(e) => Err(e),
             };
+            if let Err(e) = result {
+                println!("{}", e);
+                process::exit(1);
+            }
         });
         return Ok(());
     }
@@ -195,6 +430,8 @@ fn main() -> Result<()> {
     let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Request>();
     let (sender_1, receiver_1) = tokio::sync::mpsc::unbounded_channel::<RequestResponse>();
     let webserver = config.webserver;
+    let worker_config = config.clone();
+    let worker_sender = sender.clone();
     thread::Builder::new()
         .name("ascella-async".to_owned())
         .spawn(move || {
@@ -203,10 +440,31 @@ fn main() -> Result<()> {
                     tokio::spawn(start_server(sender_1.clone()));
                 }
 
-                while let Some(data) = receiver.recv().await {
-                    if let Err(e) = handle_event(data, &client, &sender_1).await {
-                        tracing::error!("{e:?}");
-                    };
+                let mut state = WorkerState::load(worker_config, worker_sender);
+                state.hotkeys = hotkeys::start(&state.config.hotkeys, state.requests.clone(), sender_1.clone());
+                sender_1
+                    .send(RequestResponse::QueueUpdated(state.queue.jobs().to_vec()))
+                    .ok();
+                let mut queue_tick = tokio::time::interval(Duration::from_secs(1));
+                // the first tick is right away so the policy is also enforced on startup
+                let mut cleanup_tick = tokio::time::interval(Duration::from_secs(60 * 60));
+                loop {
+                    tokio::select! {
+                        data = receiver.recv() => {
+                            let Some(data) = data else { break };
+                            if let Err(e) = handle_event(data, &client, &sender_1, &mut state).await {
+                                tracing::error!("{e:?}");
+                            };
+                        }
+                        _ = queue_tick.tick() => state.queue.process(&client, &sender_1, &state.requests),
+                        _ = cleanup_tick.tick() => {
+                            if state.config.retention.enabled {
+                                if let Err(e) = request_handler::cleanup(&state.config, &state.queue, false).await {
+                                    tracing::error!("Cleanup failed {e:?}");
+                                }
+                            }
+                        }
+                    }
                 }
             });
         })
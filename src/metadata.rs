@@ -0,0 +1,326 @@
+//! Removes EXIF, XMP and IPTC metadata from images without re-encoding them
+//!
+//! JPEG, PNG and WebP are rewritten at the container level so the image data is untouched, TIFF keeps its metadata
+//! in the same directory as the image layout so it is decoded and encoded again instead
+
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+
+/// PNG chunks that carry metadata, XMP is stored in an `iTXt` chunk
+const PNG_METADATA_CHUNKS: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// Returns the image without metadata, `None` when the format is not supported
+pub fn strip(data: &[u8], mime: &str) -> Result<Option<Vec<u8>>> {
+    Ok(Some(match mime {
+        "image/jpeg" => strip_jpeg(data)?,
+        "image/png" => strip_png(data)?,
+        "image/webp" => strip_webp(data)?,
+        "image/tiff" => {
+            let image = image::load_from_memory_with_format(data, image::ImageFormat::Tiff)?;
+            let mut out = Cursor::new(Vec::new());
+            image.write_to(&mut out, image::ImageOutputFormat::Tiff)?;
+            out.into_inner()
+        }
+        _ => return Ok(None),
+    }))
+}
+
+/// Drops APP1 (EXIF and XMP), APP13 (IPTC) and comment segments, the ICC profile in APP2 is kept
+///
+/// Viewers rotate photos with the EXIF orientation, so a rotated image gets an EXIF segment with only that tag back
+fn strip_jpeg(data: &[u8]) -> Result<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(anyhow!("Not a JPEG file"));
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return Err(anyhow!("Invalid JPEG marker at {pos}"));
+        }
+        let marker = data[pos + 1];
+        // markers may be padded with any number of 0xFF fill bytes
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // start of scan, everything after it is image data
+        if marker == 0xDA {
+            break;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + length;
+        if length < 2 || end > data.len() {
+            return Err(anyhow!("Truncated JPEG segment at {pos}"));
+        }
+        if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+            out.extend_from_slice(&data[pos..end]);
+        } else if marker == 0xE1 {
+            if let Some(orientation @ 2..=8) = exif_orientation(&data[pos + 4..end]) {
+                out.extend(orientation_segment(orientation));
+            }
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&data[pos..]);
+    Ok(out)
+}
+
+/// Orientation tag in the first IFD of an APP1 payload, `None` when it is not EXIF or has no orientation
+fn exif_orientation(payload: &[u8]) -> Option<u16> {
+    let tiff = payload.strip_prefix(b"Exif\0\0")?;
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let ifd = match *tiff.get(4..8)? {
+        [a, b, c, d] if big_endian => u32::from_be_bytes([a, b, c, d]),
+        [a, b, c, d] => u32::from_le_bytes([a, b, c, d]),
+        _ => return None,
+    } as usize;
+    // entries are 12 bytes, a tag, a type, a count and a value that fits in 4 bytes for a SHORT
+    (0..u16_at(ifd)? as usize)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(0x0112))
+        .and_then(|entry| u16_at(entry + 8))
+}
+
+/// APP1 segment with an EXIF block holding only the orientation
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    // big endian TIFF header with the first IFD right after it
+    let mut payload = b"Exif\0\0MM\0\x2a\0\0\0\x08".to_vec();
+    payload.extend_from_slice(&1u16.to_be_bytes());
+    // orientation tag, SHORT type, count 1 and the value padded to 4 bytes
+    payload.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01]);
+    payload.extend_from_slice(&orientation.to_be_bytes());
+    payload.extend_from_slice(&[0, 0]);
+    // no next IFD
+    payload.extend_from_slice(&[0, 0, 0, 0]);
+
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    segment.extend(payload);
+    segment
+}
+
+fn strip_png(data: &[u8]) -> Result<Vec<u8>> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    if !data.starts_with(&SIGNATURE) {
+        return Err(anyhow!("Not a PNG file"));
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&SIGNATURE);
+    let mut pos = SIGNATURE.len();
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        // length, type, data and crc
+        let end = pos + 12 + length;
+        if end > data.len() {
+            return Err(anyhow!("Truncated PNG chunk at {pos}"));
+        }
+        let kind = &data[pos + 4..pos + 8];
+        if !PNG_METADATA_CHUNKS.iter().any(|chunk| chunk.as_slice() == kind) {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+        if kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// Drops the `EXIF` and `XMP ` chunks and clears their flags in the `VP8X` header
+fn strip_webp(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err(anyhow!("Not a WebP file"));
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..12]);
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let kind = &data[pos..pos + 4];
+        let length = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        // chunks are padded to an even size
+        let end = (pos + 8 + length + length % 2).min(data.len());
+        if pos + 8 + length > data.len() {
+            return Err(anyhow!("Truncated WebP chunk at {pos}"));
+        }
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" if length > 0 => {
+                let start = out.len();
+                out.extend_from_slice(&data[pos..end]);
+                // bit 3 is EXIF and bit 2 is XMP
+                out[start + 8] &= !0b0000_1100;
+            }
+            _ => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    /// Little endian EXIF with a make tag before the orientation
+    fn exif(orientation: u16) -> Vec<u8> {
+        let mut exif = b"Exif\0\0II\x2a\0\x08\0\0\0\x02\0".to_vec();
+        exif.extend_from_slice(&[0x0F, 0x01, 0x02, 0x00, 0x04, 0x00, 0x00, 0x00, b'a', b'b', b'c', 0]);
+        exif.extend_from_slice(&[0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        exif.extend_from_slice(&orientation.to_le_bytes());
+        exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        exif
+    }
+
+    fn jpeg(segments: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        for segment in segments {
+            data.extend_from_slice(segment);
+        }
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 1, 2, 3, 0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn jpeg_metadata_is_dropped() {
+        let icc = jpeg_segment(0xE2, b"ICC_PROFILE\0");
+        let data = jpeg(&[
+            jpeg_segment(0xE1, &exif(1)),
+            jpeg_segment(0xE1, b"http://ns.adobe.com/xap/1.0/\0<x/>"),
+            jpeg_segment(0xED, b"Photoshop 3.0\0"),
+            jpeg_segment(0xFE, b"comment"),
+            icc.clone(),
+        ]);
+        assert_eq!(strip_jpeg(&data).unwrap(), jpeg(&[icc]));
+    }
+
+    #[test]
+    fn jpeg_orientation_is_kept() {
+        let data = jpeg(&[jpeg_segment(0xE1, &exif(6))]);
+        let stripped = strip_jpeg(&data).unwrap();
+        assert_eq!(stripped, jpeg(&[orientation_segment(6)]));
+        assert_eq!(exif_orientation(&orientation_segment(6)[4..]), Some(6));
+    }
+
+    #[test]
+    fn truncated_jpeg_segment() {
+        let mut data = jpeg(&[jpeg_segment(0xE0, b"JFIF\0")]);
+        data.truncate(8);
+        data[4..6].copy_from_slice(&100u16.to_be_bytes());
+        assert!(strip_jpeg(&data).is_err());
+        assert!(strip_jpeg(b"not a jpeg").is_err());
+        // a broken EXIF block is dropped instead of failing
+        assert_eq!(
+            strip_jpeg(&jpeg(&[jpeg_segment(0xE1, b"Exif\0\0MM")])).unwrap(),
+            jpeg(&[])
+        );
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        // the crc is not checked
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        for chunk in chunks {
+            data.extend_from_slice(chunk);
+        }
+        data
+    }
+
+    #[test]
+    fn png_metadata_is_dropped() {
+        let header = png_chunk(b"IHDR", &[0; 13]);
+        let image = png_chunk(b"IDAT", &[1, 2, 3]);
+        let end = png_chunk(b"IEND", &[]);
+        let data = png(&[
+            header.clone(),
+            png_chunk(b"tEXt", b"Software\0x"),
+            png_chunk(b"eXIf", &exif(6)[6..]),
+            image.clone(),
+            png_chunk(b"tIME", &[0; 7]),
+            end.clone(),
+        ]);
+        assert_eq!(strip_png(&data).unwrap(), png(&[header, image, end]));
+    }
+
+    #[test]
+    fn truncated_png_chunk() {
+        let mut data = png(&[png_chunk(b"IHDR", &[0; 13])]);
+        data.truncate(data.len() - 2);
+        assert!(strip_png(&data).is_err());
+    }
+
+    fn webp_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn webp(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend(body);
+        data
+    }
+
+    #[test]
+    fn webp_metadata_is_dropped() {
+        let vp8x = |flags: u8| webp_chunk(b"VP8X", &[flags, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // odd sized chunks are followed by a padding byte
+        let image = webp_chunk(b"VP8L", &[1, 2, 3]);
+        let data = webp(&[
+            vp8x(0b0010_1100),
+            image.clone(),
+            webp_chunk(b"EXIF", &exif(6)[6..]),
+            webp_chunk(b"XMP ", b"<x/>"),
+        ]);
+        assert_eq!(strip_webp(&data).unwrap(), webp(&[vp8x(0b0010_0000), image.clone()]));
+
+        // nothing to drop
+        let data = webp(&[vp8x(0b0001_0000), webp_chunk(b"ALPH", &[9]), image]);
+        assert_eq!(strip_webp(&data).unwrap(), data);
+    }
+
+    #[test]
+    fn truncated_webp_chunk() {
+        let mut data = webp(&[webp_chunk(b"VP8L", &[1, 2, 3, 4])]);
+        data.truncate(data.len() - 1);
+        assert!(strip_webp(&data).is_err());
+        assert!(strip_webp(b"RIFF\0\0\0\0WEBM").is_err());
+    }
+}
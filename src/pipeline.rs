@@ -0,0 +1,456 @@
+//! Processing applied to screenshots between the capture and the upload
+//!
+//! Stages run in the configured order, a failing stage is logged and skipped so a broken stage never loses a
+//! screenshot. Stages may change the path of the file, for example when converting to another format. The output
+//! settings of the profile are applied last
+
+use std::{
+    fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use image::{
+    codecs::{
+        jpeg::JpegEncoder,
+        webp::{WebPEncoder, WebPQuality},
+    },
+    imageops::FilterType,
+    ColorType, DynamicImage, ImageEncoder, ImageOutputFormat,
+};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::{
+    ascella_config::AscellaConfig,
+    metadata,
+    utils::{infer_mime, split_command},
+    watermark::WatermarkConfig,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    WebpLossless,
+    WebpLossy,
+    Jpeg,
+    Avif,
+}
+
+impl ImageFormat {
+    #[cfg(feature = "avif")]
+    pub const ALL: [ImageFormat; 5] = [Self::Png, Self::WebpLossless, Self::WebpLossy, Self::Jpeg, Self::Avif];
+    #[cfg(not(feature = "avif"))]
+    pub const ALL: [ImageFormat; 4] = [Self::Png, Self::WebpLossless, Self::WebpLossy, Self::Jpeg];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::WebpLossless => "WebP (lossless)",
+            Self::WebpLossy => "WebP (lossy)",
+            Self::Jpeg => "JPEG",
+            Self::Avif => "AVIF",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::WebpLossless | Self::WebpLossy => "webp",
+            Self::Jpeg => "jpg",
+            Self::Avif => "avif",
+        }
+    }
+
+    /// Whether the quality setting does anything
+    pub fn is_lossy(&self) -> bool {
+        matches!(self, Self::WebpLossy | Self::Jpeg | Self::Avif)
+    }
+
+    /// Format used when saving a file in place, WebP is assumed lossless so editing never loses quality
+    fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "jpg" | "jpeg" => Self::Jpeg,
+            "webp" => Self::WebpLossless,
+            "avif" => Self::Avif,
+            _ => Self::Png,
+        }
+    }
+}
+
+/// How captures are encoded before they are uploaded, set per profile
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutputSettings {
+    pub format: ImageFormat,
+    /// 1-100, only used by lossy formats
+    pub quality: u8,
+    /// longest side in pixels, 0 keeps the original size
+    pub max_dimension: u32,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            format: ImageFormat::Png,
+            quality: 85,
+            max_dimension: 0,
+        }
+    }
+}
+
+impl OutputSettings {
+    fn encode(&self, path: &Path) -> Result<PathBuf> {
+        if self.format == ImageFormat::Png && self.max_dimension == 0 {
+            return Ok(path.to_path_buf());
+        }
+        let oversized = self.max_dimension > 0 && {
+            let (width, height) = image::image_dimensions(path)?;
+            width.max(height) > self.max_dimension
+        };
+        // a convert stage can already have produced the format, encoding a lossy file again only loses quality
+        if !oversized && ImageFormat::from_path(path).extension() == self.format.extension() {
+            return Ok(path.to_path_buf());
+        }
+        let mut image = image::open(path)?;
+        if oversized {
+            image = image.resize(self.max_dimension, self.max_dimension, FilterType::Lanczos3);
+        }
+        let encoded = path.with_extension(self.format.extension());
+        save_image(&image, &encoded, self.format, self.quality)?;
+        if encoded != path {
+            fs::remove_file(path)?;
+        }
+        Ok(encoded)
+    }
+}
+
+/// How hard oxipng tries, higher presets find smaller files but take a lot longer on big screenshots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptimizePreset {
+    Fast,
+    #[default]
+    Balanced,
+    Max,
+}
+
+impl OptimizePreset {
+    pub const ALL: [OptimizePreset; 3] = [Self::Fast, Self::Balanced, Self::Max];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fast => "Fast",
+            Self::Balanced => "Balanced",
+            Self::Max => "Max",
+        }
+    }
+
+    fn options(&self, timeout: Duration) -> oxipng::Options {
+        let level = match self {
+            Self::Fast => 1,
+            Self::Balanced => 4,
+            Self::Max => 6,
+        };
+        oxipng::Options {
+            timeout: Some(timeout),
+            strip: oxipng::Headers::Safe,
+            force: true,
+            ..oxipng::Options::from_preset(level)
+        }
+    }
+}
+
+/// What the pipeline is working on, sent to the gui so slow stages can be followed and cancelled
+#[derive(Clone)]
+pub struct StageStatus {
+    pub stage: &'static str,
+    pub file: String,
+    pub started: Instant,
+    /// set for stages that can be cancelled, cancelling keeps the file as it was before the stage
+    pub cancel: Option<CancellationToken>,
+}
+
+/// Called with the stage that is starting and with `None` once the pipeline is done
+pub type StageCallback = Arc<dyn Fn(Option<StageStatus>) + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Processor {
+    /// Lossless PNG optimization with oxipng, other formats are left alone
+    Optimize {
+        timeout_ms: u64,
+        #[serde(default)]
+        preset: OptimizePreset,
+    },
+    /// Scales the image down to fit, 0 means no limit
+    Resize {
+        max_width: u32,
+        max_height: u32,
+    },
+    Convert {
+        format: ImageFormat,
+        quality: u8,
+    },
+    /// Composites the watermark from the watermark settings
+    Watermark,
+    StripMetadata,
+    /// `{file}` is replaced with the path of the image, the command has to edit the file in place
+    Command {
+        command: String,
+    },
+}
+
+impl Processor {
+    /// One of every stage with default settings
+    pub fn defaults() -> [Processor; 6] {
+        [
+            Self::Optimize {
+                timeout_ms: 100,
+                preset: OptimizePreset::Balanced,
+            },
+            Self::Resize {
+                max_width: 1920,
+                max_height: 1080,
+            },
+            Self::Convert {
+                format: ImageFormat::Jpeg,
+                quality: 85,
+            },
+            Self::Watermark,
+            Self::StripMetadata,
+            Self::Command { command: String::new() },
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Optimize { .. } => "Optimize PNG",
+            Self::Resize { .. } => "Resize",
+            Self::Convert { .. } => "Convert",
+            Self::Watermark => "Watermark",
+            Self::StripMetadata => "Strip metadata",
+            Self::Command { .. } => "Command",
+        }
+    }
+
+    fn apply_blocking(&self, path: &Path, watermark: &WatermarkConfig) -> Result<PathBuf> {
+        match self {
+            Self::Optimize { .. } => return Err(anyhow!("Optimize runs through optimize_png")),
+            Self::Resize { max_width, max_height } => {
+                let image = image::open(path)?;
+                let max_width = if *max_width == 0 { u32::MAX } else { *max_width };
+                let max_height = if *max_height == 0 { u32::MAX } else { *max_height };
+                if image.width() > max_width || image.height() > max_height {
+                    let resized = image.resize(max_width, max_height, FilterType::Lanczos3);
+                    save_image(&resized, path, ImageFormat::from_path(path), 90)?;
+                }
+            }
+            Self::Convert { format, quality } => {
+                let image = image::open(path)?;
+                let converted = path.with_extension(format.extension());
+                save_image(&image, &converted, *format, *quality)?;
+                if converted != path {
+                    fs::remove_file(path)?;
+                }
+                return Ok(converted);
+            }
+            Self::Watermark => {
+                let mut image = image::open(path)?.to_rgba8();
+                watermark.apply(&mut image)?;
+                save_image(&DynamicImage::ImageRgba8(image), path, ImageFormat::from_path(path), 90)?;
+            }
+            Self::StripMetadata => {
+                let mime = infer_mime(path)?;
+                match metadata::strip(&fs::read(path)?, &mime)? {
+                    Some(stripped) => fs::write(path, stripped)?,
+                    // the image crate never writes metadata so decoding and encoding again drops it
+                    None => save_image(&image::open(path)?, path, ImageFormat::from_path(path), 95)?,
+                }
+            }
+            Self::Command { .. } => return Err(anyhow!("Commands can't run on a blocking thread")),
+        }
+        Ok(path.to_path_buf())
+    }
+
+    async fn apply(&self, path: &Path, config: &AscellaConfig, cancel: &CancellationToken) -> Result<PathBuf> {
+        if let Self::Optimize { timeout_ms, preset } = self {
+            optimize_png(path, preset.options(Duration::from_millis(*timeout_ms)), cancel).await?;
+            return Ok(path.to_path_buf());
+        }
+        if let Self::Command { command } = self {
+            let args = split_command(command, &path.to_string_lossy())?;
+            let (program, args) = args.split_first().ok_or_else(|| anyhow!("No command set"))?;
+            let output = Command::new(program).args(args).output().await?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "{program} failed {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            return Ok(path.to_path_buf());
+        }
+
+        let stage = self.clone();
+        let path = path.to_path_buf();
+        let watermark = config.watermark.clone();
+        tokio::task::spawn_blocking(move || stage.apply_blocking(&path, &watermark)).await?
+    }
+}
+
+/// Optimizes the PNG on a blocking thread, oxipng can't be interrupted so cancelling stops waiting for it and leaves
+/// the file alone, the abandoned work still ends once the timeout is reached
+async fn optimize_png(path: &Path, options: oxipng::Options, cancel: &CancellationToken) -> Result<()> {
+    if infer_mime(path)? != "image/png" {
+        return Ok(());
+    }
+    let file = tokio::fs::read(path).await?;
+    let before = file.len();
+    let task = tokio::task::spawn_blocking(move || oxipng::optimize_from_memory(&file, &options));
+    let buf = tokio::select! {
+        result = task => result??,
+        _ = cancel.cancelled() => {
+            info!("Optimizing {} was cancelled", path.display());
+            return Ok(());
+        }
+    };
+    info!("Optimized PNG before: {before} after: {}", buf.len());
+    tokio::fs::write(path, buf).await?;
+    Ok(())
+}
+
+/// Runs every stage on the file, then encodes it with the output settings of the profile, returns where the
+/// processed file ended up
+pub async fn run(mut path: PathBuf, config: &AscellaConfig, on_stage: Option<StageCallback>) -> PathBuf {
+    let file = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    for stage in &config.pipeline {
+        let start = Instant::now();
+        let cancel = CancellationToken::new();
+        if let Some(on_stage) = &on_stage {
+            on_stage(Some(StageStatus {
+                stage: stage.name(),
+                file: file.clone(),
+                started: start,
+                cancel: matches!(stage, Processor::Optimize { .. }).then(|| cancel.clone()),
+            }));
+        }
+        match stage.apply(&path, config, &cancel).await {
+            Ok(new_path) => {
+                info!("{} finished in {}ms", stage.name(), start.elapsed().as_millis());
+                path = new_path;
+            }
+            Err(e) => error!(
+                "{} failed after {}ms, skipping it {e:?}",
+                stage.name(),
+                start.elapsed().as_millis()
+            ),
+        }
+    }
+
+    let output = config.profile().output.clone();
+    let start = Instant::now();
+    let original = path.clone();
+    let encoded = tokio::task::spawn_blocking(move || output.encode(&original)).await;
+    match encoded.map_err(anyhow::Error::from).and_then(|result| result) {
+        Ok(new_path) => {
+            if new_path != path {
+                info!("Encoded {} in {}ms", new_path.display(), start.elapsed().as_millis());
+            }
+            path = new_path;
+        }
+        Err(e) => error!("Encoding the output failed, uploading the original {e:?}"),
+    }
+    if let Some(on_stage) = &on_stage {
+        on_stage(None);
+    }
+    path
+}
+
+/// Saves the image, `quality` is used by lossy formats
+///
+/// The image is written next to `path` first and moved over it once encoding worked, so a failing encoder never
+/// destroys the capture it was given
+fn save_image(image: &DynamicImage, path: &Path, format: ImageFormat, quality: u8) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{name}.tmp"));
+    let written = fs::File::create(&temp)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| {
+            let mut file = BufWriter::new(file);
+            encode_image(image, &mut file, format, quality)?;
+            file.into_inner().map_err(|e| e.into_error())?;
+            Ok(())
+        })
+        .and_then(|()| Ok(fs::rename(&temp, path)?));
+    if written.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+    written
+}
+
+fn encode_image(image: &DynamicImage, file: &mut BufWriter<fs::File>, format: ImageFormat, quality: u8) -> Result<()> {
+    let quality = quality.clamp(1, 100);
+    let (width, height) = (image.width(), image.height());
+    match format {
+        ImageFormat::Png => image.write_to(file, ImageOutputFormat::Png)?,
+        ImageFormat::Jpeg => {
+            let rgb = image.to_rgb8();
+            JpegEncoder::new_with_quality(file, quality).write_image(&rgb, width, height, ColorType::Rgb8)?;
+        }
+        ImageFormat::WebpLossless | ImageFormat::WebpLossy => {
+            let webp_quality = match format {
+                ImageFormat::WebpLossy => WebPQuality::lossy(quality),
+                _ => WebPQuality::lossless(),
+            };
+            let rgba = image.to_rgba8();
+            WebPEncoder::new_with_quality(file, webp_quality).write_image(&rgba, width, height, ColorType::Rgba8)?;
+        }
+        #[cfg(feature = "avif")]
+        ImageFormat::Avif => {
+            let rgba = image.to_rgba8();
+            image::codecs::avif::AvifEncoder::new_with_speed_quality(file, 8, quality).write_image(
+                &rgba,
+                width,
+                height,
+                ColorType::Rgba8,
+            )?;
+        }
+        #[cfg(not(feature = "avif"))]
+        ImageFormat::Avif => return Err(anyhow!("This build has no AVIF support, build with --features avif")),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_encode_keeps_the_original() {
+        let dir = std::env::temp_dir().join(format!("ascella-pipeline-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("capture.jpg");
+        fs::write(&path, "original").unwrap();
+
+        // JPEG can't be wider than 65535 pixels
+        let wide = DynamicImage::new_rgb8(70_000, 1);
+        assert!(save_image(&wide, &path, ImageFormat::Jpeg, 90).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+
+        save_image(&DynamicImage::new_rgb8(2, 2), &path, ImageFormat::Jpeg, 90).unwrap();
+        assert!(image::open(&path).is_ok());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).ok();
+    }
+}
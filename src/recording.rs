@@ -0,0 +1,310 @@
+//! Screen recording with an external recorder
+//!
+//! The recorder runs until it is stopped with SIGINT, which both wf-recorder and ffmpeg handle by finishing the file.
+//! The recording is converted to GIF or WebM with ffmpeg afterwards when asked to
+
+use std::{
+    fs::{self, File},
+    io::ErrorKind,
+    path::PathBuf,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::{Child, Command};
+use tracing::info;
+
+use crate::{
+    screenshots::select_area,
+    utils::{ascella_dir, split_command},
+};
+
+#[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum RecorderType {
+    /// wlroots compositors, areas are selected with slurp
+    #[default]
+    WfRecorder,
+    /// X11 through x11grab, areas are selected with slop
+    Ffmpeg,
+    Custom {
+        area: String,
+        screen: String,
+    },
+}
+
+impl RecorderType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::WfRecorder => "wf-recorder",
+            Self::Ffmpeg => "ffmpeg",
+            Self::Custom { .. } => "custom",
+        }
+    }
+
+    /// Program and arguments that record into `file` until they get SIGINT
+    async fn command(&self, file: &str, area: bool, fps: u32) -> Result<Vec<String>> {
+        let args = match self {
+            Self::WfRecorder => {
+                let mut args = vec!["wf-recorder".to_string(), "-r".to_string(), fps.to_string()];
+                if area {
+                    args.push("-g".to_string());
+                    args.push(select_area("slurp", &[]).await?);
+                }
+                args.extend(["-f".to_string(), file.to_string()]);
+                args
+            }
+            Self::Ffmpeg => {
+                let display = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_string());
+                let mut args = vec![
+                    "ffmpeg".to_string(),
+                    "-y".to_string(),
+                    "-loglevel".to_string(),
+                    "error".to_string(),
+                    "-f".to_string(),
+                    "x11grab".to_string(),
+                    "-framerate".to_string(),
+                    fps.to_string(),
+                ];
+                if area {
+                    let geometry = select_area("slop", &["-f", "%w %h %x %y"]).await?;
+                    let [width, height, x, y]: [&str; 4] = geometry
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .try_into()
+                        .map_err(|_| anyhow!("Unexpected slop output {geometry}"))?;
+                    args.extend([
+                        "-video_size".to_string(),
+                        // libx264 needs even dimensions
+                        format!("{}x{}", even(width), even(height)),
+                        "-i".to_string(),
+                        format!("{display}+{x},{y}"),
+                    ]);
+                } else {
+                    args.extend(["-i".to_string(), display]);
+                }
+                args.extend(["-c:v", "libx264", "-preset", "ultrafast", "-pix_fmt", "yuv420p"].map(String::from));
+                args.push(file.to_string());
+                args
+            }
+            Self::Custom {
+                area: area_command,
+                screen,
+            } => {
+                let command = if area { area_command } else { screen };
+                split_command(command, file)?
+            }
+        };
+        if args.is_empty() {
+            return Err(anyhow!("No recording command set for {}", self.name()));
+        }
+        Ok(args)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingFormat {
+    /// keep the mp4 the recorder made
+    #[default]
+    Mp4,
+    Webm,
+    Gif,
+}
+
+impl RecordingFormat {
+    pub const ALL: [RecordingFormat; 3] = [Self::Mp4, Self::Webm, Self::Gif];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "MP4",
+            Self::Webm => "WebM",
+            Self::Gif => "GIF",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Webm => "webm",
+            Self::Gif => "gif",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    pub recorder: RecorderType,
+    pub format: RecordingFormat,
+    pub fps: u32,
+    /// frame rate of converted GIFs, they get huge at the recording frame rate
+    pub gif_fps: u32,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            recorder: RecorderType::WfRecorder,
+            format: RecordingFormat::Mp4,
+            fps: 30,
+            gif_fps: 15,
+        }
+    }
+}
+
+/// A running recorder
+pub struct Recording {
+    child: Child,
+    path: PathBuf,
+    pub started: Instant,
+    config: RecordingConfig,
+}
+
+impl Recording {
+    /// Starts recording the whole screen or an area the user selects first into `path`, which has to be an mp4
+    pub async fn start(config: &RecordingConfig, area: bool, path: PathBuf) -> Result<Self> {
+        let args = config
+            .recorder
+            .command(&path.to_string_lossy(), area, config.fps.max(1))
+            .await?;
+
+        let mut command = std::process::Command::new(&args[0]);
+        command
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(File::create(log_path())?);
+        // keep ctrl+c in the terminal from reaching the recorder, it is stopped through `stop`
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut command = Command::from(command);
+        command.kill_on_drop(true);
+        let mut child = command.spawn().map_err(|e| {
+            if e.kind() == ErrorKind::NotFound {
+                anyhow!(
+                    "{} is not installed\nplease install it and make sure its added to your path",
+                    args[0]
+                )
+            } else {
+                anyhow!("Failed starting {} {e}", args[0])
+            }
+        })?;
+
+        // catch recorders that exit right away because of bad arguments or a missing display
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        if let Some(status) = child.try_wait()? {
+            return Err(anyhow!("{} exited with {status}\n{}", args[0], recorder_log()));
+        }
+        info!("Recording to {}", path.display());
+        Ok(Self {
+            child,
+            path,
+            started: Instant::now(),
+            config: config.clone(),
+        })
+    }
+
+    /// Stops the recorder and converts the recording, returns the file to upload
+    pub async fn stop(mut self) -> Result<PathBuf> {
+        if self.child.try_wait()?.is_none() {
+            interrupt(&mut self.child).await?;
+        }
+        let status = match tokio::time::timeout(Duration::from_secs(10), self.child.wait()).await {
+            Ok(status) => status?,
+            Err(_) => {
+                self.child.kill().await?;
+                return Err(anyhow!("The recorder did not stop, killed it"));
+            }
+        };
+        // recorders exit with the signal or 255 after an interrupt, only a missing file is an actual failure
+        if !self.path.exists() {
+            return Err(anyhow!("Recording failed {status}\n{}", recorder_log()));
+        }
+        info!(
+            "Recorded {} in {}s",
+            self.path.display(),
+            self.started.elapsed().as_secs()
+        );
+        convert(self.path, &self.config).await
+    }
+}
+
+fn even(size: &str) -> u32 {
+    size.parse::<u32>().unwrap_or(2).max(2) & !1
+}
+
+#[cfg(unix)]
+async fn interrupt(child: &mut Child) -> Result<()> {
+    let pid = child.id().ok_or_else(|| anyhow!("The recorder already exited"))?;
+    // 0 or a negative pid would signal a whole process group instead of the recorder
+    let pid = libc::pid_t::try_from(pid)
+        .ok()
+        .filter(|pid| *pid > 0)
+        .ok_or_else(|| anyhow!("Invalid recorder pid {pid}"))?;
+    // same as ctrl+c, recorders finish writing the file before they exit
+    // SAFETY: kill only sends a signal and touches no memory, the pid is a single positive process id
+    if unsafe { libc::kill(pid, libc::SIGINT) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn interrupt(child: &mut Child) -> Result<()> {
+    child.start_kill()?;
+    Ok(())
+}
+
+async fn convert(path: PathBuf, config: &RecordingConfig) -> Result<PathBuf> {
+    let filter = match config.format {
+        RecordingFormat::Mp4 => return Ok(path),
+        RecordingFormat::Gif => vec![
+            "-vf".to_string(),
+            format!(
+                "fps={},split[a][b];[a]palettegen[p];[b][p]paletteuse",
+                config.gif_fps.max(1)
+            ),
+        ],
+        RecordingFormat::Webm => ["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "35", "-deadline", "realtime"]
+            .map(String::from)
+            .to_vec(),
+    };
+    let converted = path.with_extension(config.format.extension());
+    let start = Instant::now();
+    let output = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(&path)
+        .args(filter)
+        .arg(&converted)
+        .output()
+        .await
+        .map_err(|e| anyhow!("Failed running ffmpeg to convert the recording {e}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Converting the recording failed, it was kept at {}\n{}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    info!(
+        "Converted recording to {} in {}ms",
+        config.format.name(),
+        start.elapsed().as_millis()
+    );
+    fs::remove_file(&path)?;
+    Ok(converted)
+}
+
+fn log_path() -> PathBuf {
+    ascella_dir().join("recorder.log")
+}
+
+/// Last lines the recorder wrote to stderr
+fn recorder_log() -> String {
+    let log = fs::read_to_string(log_path()).unwrap_or_default();
+    let lines: Vec<&str> = log.lines().collect();
+    lines[lines.len().saturating_sub(5)..].join("\n")
+}
@@ -0,0 +1,389 @@
+//! Blacks out sensitive parts of screenshots before they are uploaded
+//!
+//! Regions come from fixed rectangles and from regex rules matched against the output of an OCR command. The
+//! command has to print tesseract style TSV (`tesseract {file} - tsv`) so any OCR tool can be plugged in with a
+//! small wrapper script
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use eframe::egui::{self, pos2, vec2, Color32, Rect, Stroke, TextureHandle, TextureOptions};
+use image::{Rgba, RgbaImage};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::info;
+
+use crate::utils::split_command;
+
+/// Padding in pixels added around text matched by a rule
+const TEXT_PADDING: u32 = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    // regions come from the config and the OCR command, so sizes can be anything
+    fn right(&self) -> u32 {
+        self.x.saturating_add(self.width)
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+
+    fn union(self, other: Region) -> Region {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Region {
+            x,
+            y,
+            width: self.right().max(other.right()) - x,
+            height: self.bottom().max(other.bottom()) - y,
+        }
+    }
+
+    fn padded(self, padding: u32) -> Region {
+        let x = self.x.saturating_sub(padding);
+        let y = self.y.saturating_sub(padding);
+        Region {
+            x,
+            y,
+            width: self.right().saturating_add(padding) - x,
+            height: self.bottom().saturating_add(padding) - y,
+        }
+    }
+}
+
+/// A fixed rectangle that is always blacked out, for example a bookmarks bar or a chat sidebar
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RedactionPreset {
+    pub name: String,
+    pub enabled: bool,
+    pub region: Region,
+}
+
+impl Default for RedactionPreset {
+    fn default() -> Self {
+        Self {
+            name: "preset".to_string(),
+            enabled: true,
+            region: Region {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 100,
+            },
+        }
+    }
+}
+
+/// Text matching the pattern is blacked out
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RedactionRule {
+    pub name: String,
+    pub enabled: bool,
+    pub pattern: String,
+}
+
+impl Default for RedactionRule {
+    fn default() -> Self {
+        Self {
+            name: "rule".to_string(),
+            enabled: true,
+            pattern: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    /// `{file}` is replaced with the screenshot path, has to print tesseract style TSV
+    pub ocr_command: String,
+    pub presets: Vec<RedactionPreset>,
+    pub rules: Vec<RedactionRule>,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ocr_command: "tesseract {file} - tsv".to_string(),
+            presets: Vec::new(),
+            rules: vec![
+                RedactionRule {
+                    name: "Email".to_string(),
+                    enabled: true,
+                    pattern: r"[\w.+-]+@[\w-]+\.[\w.-]+".to_string(),
+                },
+                RedactionRule {
+                    name: "Token".to_string(),
+                    enabled: true,
+                    pattern: r"[A-Za-z0-9_\-]{32,}".to_string(),
+                },
+            ],
+        }
+    }
+}
+
+impl RedactionConfig {
+    fn active_rules(&self) -> Result<Vec<Regex>> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.enabled && !rule.pattern.is_empty())
+            .map(|rule| Regex::new(&rule.pattern).map_err(|e| anyhow!("Invalid redaction rule {} {e}", rule.name)))
+            .collect()
+    }
+}
+
+/// Finds every region of the image that should be blacked out
+pub async fn find_regions(path: &Path, config: &RedactionConfig) -> Result<Vec<Region>> {
+    let mut regions: Vec<Region> = config
+        .presets
+        .iter()
+        .filter(|preset| preset.enabled)
+        .map(|preset| preset.region)
+        .collect();
+
+    let rules = config.active_rules()?;
+    if !rules.is_empty() && !config.ocr_command.trim().is_empty() {
+        let words = ocr(path, &config.ocr_command).await?;
+        regions.extend(match_words(&words, &rules));
+    }
+    Ok(regions)
+}
+
+/// Blacks out the configured regions in place and returns how many were redacted
+pub async fn redact(path: &Path, config: &RedactionConfig) -> Result<usize> {
+    if !config.enabled {
+        return Ok(0);
+    }
+    let regions = find_regions(path, config).await?;
+    if regions.is_empty() {
+        return Ok(0);
+    }
+    let path = path.to_path_buf();
+    let count = regions.len();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let mut image = image::open(&path)?.to_rgba8();
+        for region in &regions {
+            black_out(&mut image, *region);
+        }
+        image.save(&path)?;
+        Ok(())
+    })
+    .await??;
+    info!("Redacted {count} regions");
+    Ok(count)
+}
+
+fn black_out(image: &mut RgbaImage, region: Region) {
+    let right = region.right().min(image.width());
+    let bottom = region.bottom().min(image.height());
+    for y in region.y..bottom {
+        for x in region.x..right {
+            image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+        }
+    }
+}
+
+struct Word {
+    /// page, block, paragraph and line numbers
+    line: [u32; 4],
+    region: Region,
+    text: String,
+}
+
+async fn ocr(path: &Path, command: &str) -> Result<Vec<Word>> {
+    let args = split_command(command, &path.to_string_lossy())?;
+    let (program, args) = args.split_first().ok_or_else(|| anyhow!("No OCR command set"))?;
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| anyhow!("Failed running OCR command {program} {e}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "OCR command failed {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_tsv(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses `level page_num block_num par_num line_num word_num left top width height conf text` rows
+fn parse_tsv(tsv: &str) -> Vec<Word> {
+    tsv.lines()
+        .filter_map(|row| {
+            let columns: Vec<&str> = row.split('\t').collect();
+            if columns.len() < 12 || columns[11].trim().is_empty() {
+                return None;
+            }
+            let num = |i: usize| columns[i].trim().parse::<u32>().ok();
+            Some(Word {
+                line: [num(1)?, num(2)?, num(3)?, num(4)?],
+                region: Region {
+                    x: num(6)?,
+                    y: num(7)?,
+                    width: num(8)?,
+                    height: num(9)?,
+                },
+                text: columns[11].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Matches rules against whole lines so patterns can span several words
+fn match_words(words: &[Word], rules: &[Regex]) -> Vec<Region> {
+    let mut regions = Vec::new();
+    for line in words.chunk_by(|a, b| a.line == b.line) {
+        let mut text = String::new();
+        let mut spans = Vec::new();
+        for word in line {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            spans.push((text.len(), text.len() + word.text.len()));
+            text.push_str(&word.text);
+        }
+        for rule in rules {
+            for found in rule.find_iter(&text) {
+                let region = line
+                    .iter()
+                    .zip(&spans)
+                    .filter(|(_, (start, end))| *start < found.end() && found.start() < *end)
+                    .map(|(word, _)| word.region)
+                    .reduce(Region::union);
+                regions.extend(region.map(|region| region.padded(TEXT_PADDING)));
+            }
+        }
+    }
+    regions
+}
+
+/// Shows what a redaction pass would black out without touching the file
+pub struct RedactionPreview {
+    image: RgbaImage,
+    regions: Vec<Region>,
+    texture: Option<TextureHandle>,
+}
+
+impl RedactionPreview {
+    pub fn new(path: &Path, regions: Vec<Region>) -> Result<Self> {
+        Ok(Self {
+            image: image::open(path)?.to_rgba8(),
+            regions,
+            texture: None,
+        })
+    }
+
+    /// Returns false once the window is closed
+    pub fn show(&mut self, ctx: &egui::Context) -> bool {
+        let image = &self.image;
+        let texture = self.texture.get_or_insert_with(|| {
+            ctx.load_texture(
+                "redaction-preview",
+                egui::ColorImage::from_rgba_unmultiplied(
+                    [image.width() as usize, image.height() as usize],
+                    image.as_raw(),
+                ),
+                TextureOptions::LINEAR,
+            )
+        });
+
+        let mut open = true;
+        egui::Window::new("Redaction preview")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("{} regions will be blacked out", self.regions.len()));
+                let (w, h) = (image.width() as f32, image.height() as f32);
+                let max = (ctx.screen_rect().size() - vec2(60.0, 120.0)).max(vec2(100.0, 100.0));
+                let scale = (max.x / w).min(max.y / h).min(1.0);
+                let (response, painter) = ui.allocate_painter(vec2(w, h) * scale, egui::Sense::hover());
+                let canvas = response.rect;
+                painter.image(
+                    texture.id(),
+                    canvas,
+                    Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+                    Color32::WHITE,
+                );
+                for region in &self.regions {
+                    let rect = Rect::from_min_size(
+                        canvas.min + vec2(region.x as f32, region.y as f32) * scale,
+                        vec2(region.width as f32, region.height as f32) * scale,
+                    );
+                    painter.rect(
+                        rect,
+                        0.0,
+                        Color32::from_black_alpha(200),
+                        Stroke::new(1.0, Color32::RED),
+                    );
+                }
+            });
+        open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TSV: &str = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext
+4\t1\t1\t1\t1\t0\t10\t10\t200\t20\t-1\t
+5\t1\t1\t1\t1\t1\t10\t10\t50\t20\t96\tpassword:
+5\t1\t1\t1\t1\t2\t70\t10\t80\t20\t95\thunter2
+5\t1\t1\t1\t2\t1\t10\t40\t60\t20\t91\tuser@example.com
+5\t1\t1\t1\t2\t2\tx\t40\t60\t20\t91\tbroken
+";
+
+    fn region(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region { x, y, width, height }
+    }
+
+    #[test]
+    fn tsv_rows_with_text() {
+        let words = parse_tsv(TSV);
+        let texts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+        assert_eq!(texts, ["password:", "hunter2", "user@example.com"]);
+        assert_eq!(words[1].line, [1, 1, 1, 1]);
+        assert_eq!(words[1].region, region(70, 10, 80, 20));
+        assert!(parse_tsv("").is_empty());
+    }
+
+    #[test]
+    fn rules_match_across_words() {
+        let words = parse_tsv(TSV);
+        let rules = [Regex::new(r"password: \S+").unwrap(), Regex::new(r"\S+@\S+").unwrap()];
+        assert_eq!(
+            match_words(&words, &rules),
+            [region(8, 8, 144, 24), region(8, 38, 64, 24)]
+        );
+        // matches never span lines
+        assert!(match_words(&words, &[Regex::new("hunter2 user").unwrap()]).is_empty());
+    }
+
+    #[test]
+    fn huge_regions_saturate() {
+        let huge = region(u32::MAX - 1, 5, u32::MAX, u32::MAX);
+        assert_eq!(region(0, 0, 1, 1).union(huge), region(0, 0, u32::MAX, u32::MAX));
+        assert_eq!(huge.padded(TEXT_PADDING).right(), u32::MAX);
+
+        let mut image = RgbaImage::new(4, 4);
+        black_out(&mut image, region(2, 2, u32::MAX, u32::MAX));
+        assert_eq!(image.get_pixel(3, 3), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(1, 1), &Rgba([0, 0, 0, 0]));
+        black_out(&mut image, region(10, 10, 5, 5));
+    }
+}
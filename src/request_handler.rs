@@ -1,21 +1,86 @@
-use std::{ffi::OsStr, io::ErrorKind, os::unix::prelude::OsStrExt, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use anyhow::anyhow;
+use bytes::{Bytes, BytesMut};
 use egui_notify::Toast;
+use futures_util::{stream, Stream, StreamExt};
 use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue},
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
     multipart::{self, Part},
+    Method, StatusCode,
 };
 use tokio::{process::Command, sync::mpsc::UnboundedSender};
+use tokio_util::{
+    codec::{BytesCodec, FramedRead},
+    sync::CancellationToken,
+};
 use tracing::info;
 
 use crate::{
-    ascella_config::AscellaConfig, clipboard::copy, utils::ascella_notif, Request, RequestResponse, UploadResponse,
+    ascella_config::{AscellaConfig, UploaderProfile},
+    clipboard::{self, copy},
+    filename,
+    hotkeys::{self, HotkeyListener},
+    local_save, metadata,
+    pipeline::{self, StageCallback, StageStatus},
+    recording::{Recording, RecordingFormat},
+    redaction,
+    retention::{self, CleanupReport},
+    screenshots::{ScreenshotCommand, ScreenshotType},
+    sxcu::{self, BodyType},
+    upload_history::{self, HistoryEntry},
+    upload_queue::UploadQueue,
+    utils::{ascella_notif, format_bytes, infer_mime},
+    Request, RequestResponse, SendScreenshot, UploadResponse,
 };
 
+/// State kept by the async worker between events
+pub struct WorkerState {
+    pub queue: UploadQueue,
+    /// the running recording and the config it gets uploaded with
+    pub recording: Option<(Recording, AscellaConfig)>,
+    /// last saved config, used for the periodic cleanup
+    pub config: AscellaConfig,
+    /// cancels the delayed capture that is counting down
+    pub countdown: Option<CancellationToken>,
+    /// sends delayed captures and hotkey presses back to the worker
+    pub requests: UnboundedSender<Request>,
+    pub hotkeys: Option<HotkeyListener>,
+}
+
+impl WorkerState {
+    pub fn load(config: AscellaConfig, requests: UnboundedSender<Request>) -> Self {
+        Self {
+            queue: UploadQueue::load(),
+            recording: None,
+            config,
+            countdown: None,
+            requests,
+            hotkeys: None,
+        }
+    }
+}
+
+/// A capture waiting for its delay to run out
+#[derive(Debug, Clone)]
+pub struct Countdown {
+    pub mode: &'static str,
+    pub ends: Instant,
+    pub cancel: CancellationToken,
+}
+
 pub async fn handle_event(
     data: Request,
     client: &reqwest::Client,
     sender: &UnboundedSender<RequestResponse>,
+    state: &mut WorkerState,
 ) -> anyhow::Result<()> {
     match data {
         Request::DoRequest { request, r_type } => {
@@ -33,58 +98,155 @@ pub async fn handle_event(
             r_type,
             send,
             config,
-            print,
-        } => {
-            let cmd = r_type.cmd_from_type(send);
-            let mut args = cmd.1.split_whitespace();
-
-            let command = match Command::new(args.next().unwrap()).args(args).output().await {
-                Ok(r) => r,
-                Err(e) => {
-                    let msg = if e.kind() == ErrorKind::NotFound {
-                        format!(
-                            "{} is not installed\nplease install it and make sure its added to your path",
-                            r_type.name()
-                        )
-                    } else {
-                        format!("Failed executing screenshot command\n{:?}", e)
-                    };
-                    tracing::error!("Error starting screenshot process {e:?}, {}", cmd.1);
-                    sender.send(RequestResponse::Toast(Toast::error(msg))).ok();
-                    return Ok(());
-                }
-            };
-            if !command.status.success() {
+            delay,
+        } if !delay.is_zero() => start_countdown(r_type, send, config, delay, sender, state),
+        Request::Screenshot {
+            r_type, send, config, ..
+        } => match capture(&r_type, send, &config).await {
+            Ok(path) if config.profile().annotate => {
                 sender
-                    .send(RequestResponse::Toast(Toast::error(format!(
-                        "Failed executing screenshot command\n{command:?}",
-                    ))))
+                    .send(RequestResponse::Annotate {
+                        path,
+                        config: Box::new(config),
+                    })
                     .ok();
-                tracing::error!("Error executing screenshot command {command:?}");
-                return Ok(());
             }
-
-            match upload_file(PathBuf::from(cmd.0), &config, client, print).await {
-                Ok(res) => {
+            Ok(path) => {
+                let path = pipeline::run(path, &config, Some(stage_status(sender))).await;
+                upload_or_save(path, config, client, sender, state).await;
+            }
+            Err(e) => {
+                sender.send(RequestResponse::Toast(Toast::error(e.to_string()))).ok();
+            }
+        },
+        Request::UploadCapture { path, config } => {
+            let path = pipeline::run(path, &config, Some(stage_status(sender))).await;
+            upload_or_save(path, config, client, sender, state).await;
+        }
+        Request::Upload { paths, config } => {
+            for path in paths {
+                state.queue.push(path, config.clone(), sender);
+            }
+            state.queue.process(client, sender, &state.requests);
+        }
+        Request::UploadClipboard(config) => match clipboard::paste_to_file(&config).await {
+            Ok(path) => {
+                state.queue.push(path, config, sender);
+                state.queue.process(client, sender, &state.requests);
+            }
+            Err(e) => {
+                sender.send(RequestResponse::Toast(Toast::error(e.to_string()))).ok();
+            }
+        },
+        Request::RetryUpload { id, config } => {
+            state.queue.retry(id, config, sender);
+            state.queue.process(client, sender, &state.requests);
+        }
+        Request::CancelUpload(id) => state.queue.cancel(id, sender),
+        Request::UploadFinished { id, result } => state.queue.finish(id, result, sender),
+        Request::DeleteUpload(entry) => {
+            match delete_upload(&entry, &state.config, client).await {
+                Ok(()) => {
                     sender
-                        .send(RequestResponse::Toast(Toast::success(format!(
-                            "Image uploaded {}",
-                            res.url
-                        ))))
+                        .send(RequestResponse::Toast(Toast::success(format!("Deleted {}", entry.url))))
                         .ok();
                 }
                 Err(e) => {
                     sender
                         .send(RequestResponse::Toast(Toast::error(format!(
-                            "Failed uploading image\n{:?}",
-                            e
+                            "Failed deleting upload\n{e}"
                         ))))
                         .ok();
                 }
             }
+            sender.send(RequestResponse::HistoryUpdated).ok();
+        }
+        Request::PreviewRedaction { path, config } => match redaction::find_regions(&path, &config).await {
+            Ok(regions) => {
+                sender.send(RequestResponse::RedactionPreview { path, regions }).ok();
+            }
+            Err(e) => {
+                sender.send(RequestResponse::Toast(Toast::error(e.to_string()))).ok();
+            }
+        },
+        Request::StartRecording { area, config } => {
+            if state.recording.is_some() {
+                sender
+                    .send(RequestResponse::Toast(Toast::warning("Already recording".to_string())))
+                    .ok();
+                return Ok(());
+            }
+            let recording = match filename::capture_path(&config, "recording", "mp4").await {
+                Ok(path) => Recording::start(&config.recording, area, path).await,
+                Err(e) => Err(e),
+            };
+            match recording {
+                Ok(recording) => {
+                    sender.send(RequestResponse::Recording(Some(recording.started))).ok();
+                    state.recording = Some((recording, config));
+                }
+                Err(e) => {
+                    sender.send(RequestResponse::Toast(Toast::error(e.to_string()))).ok();
+                }
+            }
+        }
+        Request::StopRecording => {
+            let Some((recording, config)) = state.recording.take() else {
+                return Ok(());
+            };
+            sender.send(RequestResponse::Recording(None)).ok();
+            let format = config.recording.format;
+            if format != RecordingFormat::Mp4 {
+                sender
+                    .send(RequestResponse::PipelineStage(Some(StageStatus {
+                        stage: "Converting to",
+                        file: format.name().to_string(),
+                        started: Instant::now(),
+                        cancel: None,
+                    })))
+                    .ok();
+            }
+            let result = recording.stop().await;
+            sender.send(RequestResponse::PipelineStage(None)).ok();
+            match result {
+                Ok(path) => upload_or_save(path, config, client, sender, state).await,
+                Err(e) => {
+                    sender.send(RequestResponse::Toast(Toast::error(e.to_string()))).ok();
+                }
+            }
+        }
+        Request::Cleanup { config, dry_run } => match cleanup(&config, &state.queue, dry_run).await {
+            Ok(report) => {
+                if !dry_run {
+                    sender
+                        .send(RequestResponse::Toast(Toast::success(format!(
+                            "Removed {} captures, freed {}",
+                            report.removed.len(),
+                            format_bytes(report.freed())
+                        ))))
+                        .ok();
+                }
+                sender.send(RequestResponse::CleanupReport(report)).ok();
+            }
+            Err(e) => {
+                sender
+                    .send(RequestResponse::Toast(Toast::error(format!("Cleanup failed\n{e}"))))
+                    .ok();
+            }
+        },
+        Request::Hotkey(action) => {
+            state.requests.send(action.request(&state.config)).ok();
         }
         Request::SaveConfig(config) => {
             config.save().await?;
+            if config.hotkeys != state.config.hotkeys {
+                // the old listener has to release its keys before they can be registered again
+                if let Some(listener) = state.hotkeys.take() {
+                    listener.stop().await;
+                }
+                state.hotkeys = hotkeys::start(&config.hotkeys, state.requests.clone(), sender.clone());
+            }
+            state.config = config;
             sender
                 .send(RequestResponse::Toast(Toast::success("Config saved".to_string())))
                 .ok();
@@ -93,68 +255,434 @@ pub async fn handle_event(
     Ok(())
 }
 
+/// Waits out the delay on its own task so the worker keeps handling events, then sends the screenshot back without
+/// it, a new delayed capture replaces the one counting down
+fn start_countdown(
+    r_type: ScreenshotType,
+    send: SendScreenshot,
+    config: AscellaConfig,
+    delay: Duration,
+    sender: &UnboundedSender<RequestResponse>,
+    state: &mut WorkerState,
+) {
+    if let Some(previous) = state.countdown.take() {
+        previous.cancel();
+    }
+    let cancel = CancellationToken::new();
+    state.countdown = Some(cancel.clone());
+    sender
+        .send(RequestResponse::Countdown(Some(Countdown {
+            mode: send.name(),
+            ends: Instant::now() + delay,
+            cancel: cancel.clone(),
+        })))
+        .ok();
+    if config.notifications_enabled {
+        let body = format!("Capturing the {} in {}s", send.name(), delay.as_secs());
+        if let Err(e) = ascella_notif().body(&body).show() {
+            tracing::error!("Failed showing the countdown notification {e:?}");
+        }
+    }
+
+    let sender = sender.clone();
+    let requests = state.requests.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {
+                sender.send(RequestResponse::Countdown(None)).ok();
+                requests
+                    .send(Request::Screenshot {
+                        r_type,
+                        send,
+                        config,
+                        delay: Duration::ZERO,
+                    })
+                    .ok();
+            }
+            // whoever cancelled already took care of the countdown shown in the ui
+            _ = cancel.cancelled() => info!("Delayed capture cancelled"),
+        }
+    });
+}
+
+/// Applies the retention policy off the async threads, files waiting in the queue are kept
+pub async fn cleanup(config: &AscellaConfig, queue: &UploadQueue, dry_run: bool) -> anyhow::Result<CleanupReport> {
+    let protected: Vec<PathBuf> = queue.jobs().iter().map(|job| job.path.clone()).collect();
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || retention::cleanup(&config, &protected, dry_run)).await?
+}
+
+/// Queues a finished capture for upload, or saves it when uploads are turned off
+async fn upload_or_save(
+    path: PathBuf,
+    config: AscellaConfig,
+    client: &reqwest::Client,
+    sender: &UnboundedSender<RequestResponse>,
+    state: &mut WorkerState,
+) {
+    if !config.is_local_only() {
+        state.queue.push(path, config, sender);
+        state.queue.process(client, sender, &state.requests);
+        return;
+    }
+    match local_save::save(&path, &config).await {
+        Ok(saved) => {
+            sender
+                .send(RequestResponse::Toast(Toast::success(format!(
+                    "Saved to {}",
+                    saved.display()
+                ))))
+                .ok();
+            sender.send(RequestResponse::HistoryUpdated).ok();
+        }
+        Err(e) => {
+            sender
+                .send(RequestResponse::Toast(Toast::error(format!(
+                    "Failed saving the capture\n{e}"
+                ))))
+                .ok();
+        }
+    }
+}
+
+/// Takes a screenshot and runs everything that has to happen to it before it can be uploaded
+pub async fn capture(r_type: &ScreenshotType, send: SendScreenshot, config: &AscellaConfig) -> anyhow::Result<PathBuf> {
+    let path = filename::capture_path(config, send.name(), "png").await?;
+    let path = take_screenshot(r_type, send, path).await?;
+    redaction::redact(&path, &config.redaction)
+        .await
+        .map_err(|e| anyhow!("Redaction failed, not uploading the screenshot\n{e}"))?;
+    Ok(path)
+}
+
+/// Runs the screenshot tool and returns the path of the capture
+pub async fn take_screenshot(r_type: &ScreenshotType, send: SendScreenshot, path: PathBuf) -> anyhow::Result<PathBuf> {
+    let ScreenshotCommand {
+        path,
+        args,
+        env,
+        stdout,
+    } = r_type.command(send, path).await?;
+
+    let command = match Command::new(&args[0]).args(&args[1..]).envs(&env).output().await {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::error!("Error starting screenshot process {e:?}, {}", args.join(" "));
+            return Err(if e.kind() == ErrorKind::NotFound {
+                anyhow!(
+                    "{} is not installed\nplease install it and make sure its added to your path",
+                    args[0]
+                )
+            } else {
+                anyhow!("Failed executing screenshot command\n{:?}", e)
+            });
+        }
+    };
+    if !command.status.success() {
+        let stderr = String::from_utf8_lossy(&command.stderr);
+        tracing::error!("Error executing screenshot command {} {stderr}", command.status);
+        return Err(anyhow!(
+            "Failed executing screenshot command {}\n{}",
+            command.status,
+            stderr.trim()
+        ));
+    }
+    if stdout {
+        if command.stdout.is_empty() {
+            return Err(anyhow!("The screenshot command printed no image"));
+        }
+        let path = match infer::get(&command.stdout) {
+            Some(kind) => path.with_extension(kind.extension()),
+            None => path,
+        };
+        tokio::fs::write(&path, &command.stdout).await?;
+        return Ok(path);
+    }
+    Ok(path)
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadProgress {
+    pub file: String,
+    pub sent: u64,
+    pub total: u64,
+    pub bytes_per_sec: f64,
+}
+
+impl UploadProgress {
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.sent as f32 / self.total as f32
+        }
+    }
+
+    pub fn eta(&self) -> Duration {
+        if self.bytes_per_sec <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(self.total.saturating_sub(self.sent) as f64 / self.bytes_per_sec)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.sent >= self.total
+    }
+}
+
+impl fmt::Display for UploadProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:.0}% {}/{} {}/s ETA {}s",
+            self.file,
+            self.fraction() * 100.0,
+            format_bytes(self.sent),
+            format_bytes(self.total),
+            format_bytes(self.bytes_per_sec as u64),
+            self.eta().as_secs()
+        )
+    }
+}
+
+/// Why an upload was rejected, used to show a specific message and to decide if retrying makes sense
+#[derive(Debug)]
+pub enum UploadError {
+    Unauthorized(String),
+    QuotaExceeded(String),
+    PayloadTooLarge(String),
+    Server(StatusCode, String),
+    Rejected(StatusCode, String),
+    Unparseable(String),
+    /// the server could not be reached or the connection dropped
+    Network(String),
+}
+
+impl UploadError {
+    fn from_status(status: StatusCode, message: String) -> Self {
+        match status.as_u16() {
+            401 | 403 => Self::Unauthorized(message),
+            402 | 429 | 507 => Self::QuotaExceeded(message),
+            413 => Self::PayloadTooLarge(message),
+            500..=599 => Self::Server(status, message),
+            _ => Self::Rejected(status, message),
+        }
+    }
+
+    /// Only server and network errors can go away on their own, everything else needs the user to change something
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Server(..) | Self::Network(_))
+    }
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unauthorized(msg) => write!(f, "Authentication failed, check the token of your profile\n{msg}"),
+            Self::QuotaExceeded(msg) => write!(f, "Upload quota exceeded\n{msg}"),
+            Self::PayloadTooLarge(msg) => write!(f, "File is too large for this server\n{msg}"),
+            Self::Server(status, msg) => write!(f, "Server error {status}\n{msg}"),
+            Self::Rejected(status, msg) => write!(f, "Upload rejected with {status}\n{msg}"),
+            Self::Unparseable(body) => write!(f, "Could not understand the server response\n{}", truncate(body)),
+            Self::Network(msg) => write!(f, "Could not reach the server\n{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+fn truncate(text: &str) -> &str {
+    match text.char_indices().nth(200) {
+        Some((index, _)) => &text[..index],
+        None => text,
+    }
+}
+
+/// Error bodies are usually json with a message, otherwise an html page we don't want to show in full
+fn error_message_from_body(body: &str) -> String {
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(body) {
+        for key in ["message", "error", "detail"] {
+            if let Some(message) = json[key].as_str() {
+                return message.to_string();
+            }
+        }
+    }
+    truncate(body.trim()).to_string()
+}
+
+/// Forwards pipeline stages to the gui, the worker is busy until the pipeline is done so the gui cancels stages
+/// through the token instead of sending a request
+fn stage_status(sender: &UnboundedSender<RequestResponse>) -> StageCallback {
+    let sender = sender.clone();
+    Arc::new(move |status: Option<StageStatus>| {
+        sender.send(RequestResponse::PipelineStage(status)).ok();
+    })
+}
+
+pub type ProgressCallback = Arc<dyn Fn(UploadProgress) + Send + Sync>;
+
+/// Progress callback for the cli, keeps rewriting a single line on stderr
+pub fn print_progress() -> ProgressCallback {
+    Arc::new(|progress: UploadProgress| {
+        eprint!("\r{progress}    ");
+        if progress.is_done() {
+            eprintln!();
+        }
+    })
+}
+
+/// Wraps the stream into a body that reports how much of it has been read by reqwest, reports are throttled to
+/// one every 100ms
+fn progress_body<S>(stream: S, file: String, total: u64, progress: Option<ProgressCallback>) -> reqwest::Body
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+{
+    let Some(progress) = progress else {
+        return reqwest::Body::wrap_stream(stream);
+    };
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut sent = 0;
+    reqwest::Body::wrap_stream(stream.inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            sent += chunk.len() as u64;
+            if sent >= total || last_report.elapsed() >= Duration::from_millis(100) {
+                last_report = Instant::now();
+                progress(UploadProgress {
+                    file: file.clone(),
+                    sent,
+                    total,
+                    bytes_per_sec: sent as f64 / start.elapsed().as_secs_f64().max(0.001),
+                });
+            }
+        }
+    }))
+}
+
 pub async fn upload_file(
     path: PathBuf,
     config: &AscellaConfig,
     client: &reqwest::Client,
     print: bool,
+    progress: Option<ProgressCallback>,
 ) -> anyhow::Result<UploadResponse> {
-    let mut form = multipart::Form::new();
-    let filename = path.file_name().unwrap().to_string_lossy().to_string();
-    if path.extension() == Some(OsStr::from_bytes(b"png")) && config.optimize_png {
-        info!("Optimizing PNG");
-        let file = std::fs::read(&path)?;
-        let file_ln = file.len();
-        let now = std::time::Instant::now();
-        let buf = oxipng::optimize_from_memory(
-            &file,
-            &oxipng::Options {
-                timeout: Some(Duration::from_millis(config.optimize_timeout)),
-                strip: oxipng::Headers::Safe,
-                force: true,
-                ..oxipng::Options::from_preset(4)
-            },
-        )?;
-        info!(
-            "Optimized image in {}ms before: {file_ln} after: {}",
-            now.elapsed().as_millis(),
-            buf.len()
-        );
-        form = form.part("file", Part::bytes(buf).file_name(filename).mime_str("image/png")?);
+    let filename = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", path.display()))?
+        .to_string_lossy()
+        .to_string();
+    let mime = infer_mime(&path)?;
+    info!("Uploading {filename} as {mime}");
+    if print {
+        println!("Uploading {filename} ({mime})");
+    }
+    let stripped = if config.strip_metadata && mime.starts_with("image/") {
+        let data = tokio::fs::read(&path).await?;
+        metadata::strip(&data, &mime).unwrap_or_else(|e| {
+            tracing::warn!("Failed stripping metadata from {filename} {e:?}");
+            None
+        })
     } else {
-        fn file_to_body(file: tokio::fs::File) -> reqwest::Body {
-            let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new());
-
-            reqwest::Body::wrap_stream(stream)
+        None
+    };
+    let (body, size) = match stripped {
+        Some(data) => {
+            let size = data.len() as u64;
+            let chunks: Vec<std::io::Result<Bytes>> =
+                data.chunks(64 * 1024).map(|c| Ok(Bytes::copy_from_slice(c))).collect();
+            (
+                progress_body(stream::iter(chunks), filename.clone(), size, progress),
+                size,
+            )
+        }
+        None => {
+            let size = tokio::fs::metadata(&path).await?.len();
+            let file = tokio::fs::File::open(&path).await?;
+            let stream = FramedRead::new(file, BytesCodec::new()).map(|chunk| chunk.map(BytesMut::freeze));
+            (progress_body(stream, filename.clone(), size, progress), size)
         }
+    };
 
-        let file = file_to_body(tokio::fs::File::open(&path).await?);
-        form = form.part(
-            "file",
-            Part::stream(file)
-                .file_name(filename)
-                //TODO infer mime
-                .mime_str("image/png")?,
-        );
-    }
-    let mut headers = headermap_from_hashmap(config.headers.iter());
-    if !config.api_key.is_empty() {
-        headers.insert("ascella-token", HeaderValue::from_str(&config.api_key)?);
+    let profile = config.profile();
+    let mut headers = headermap_from_hashmap(profile.headers.iter());
+    if !profile.api_key.is_empty() {
+        headers.insert("ascella-token", HeaderValue::from_str(&profile.api_key)?);
     }
+    let arguments = profile
+        .arguments
+        .iter()
+        .map(|(name, value)| Ok((name, sxcu::parse_argument(value, &filename)?)))
+        .collect::<anyhow::Result<HashMap<&String, String>>>()?;
+
+    let request = client
+        .request(
+            Method::from_bytes(profile.request_method.as_bytes())?,
+            &profile.request_url,
+        )
+        .query(&profile.parameters)
+        .headers(headers);
+    let request = match profile.body {
+        BodyType::MultipartFormData => {
+            let mut form = multipart::Form::new();
+            for (name, value) in arguments {
+                form = form.text(name.clone(), value);
+            }
+            form = form.part(
+                profile.file_form_name.clone(),
+                Part::stream_with_length(body, size)
+                    .file_name(filename)
+                    .mime_str(&mime)?,
+            );
+            request.multipart(form)
+        }
+        BodyType::Binary => request.header(CONTENT_TYPE, &mime).body(body),
+        BodyType::FormUrlEncoded => request.form(&arguments),
+        BodyType::Json => {
+            let data = if profile.data.is_empty() {
+                serde_json::to_string(&arguments)?
+            } else {
+                sxcu::parse_json_data(&profile.data, &filename)?
+            };
+            request.header(CONTENT_TYPE, "application/json").body(data)
+        }
+        BodyType::None => request,
+    };
+
+    let res = request.send().await.map_err(|e| UploadError::Network(e.to_string()))?;
+    let status = res.status();
+    let res_headers = res.headers().clone();
+    let res = res.text().await.map_err(|e| UploadError::Network(e.to_string()))?;
+    tracing::debug!("Upload response {status} {}", res);
 
-    let res = client
-        .post(&config.request_url)
-        .headers(headers)
-        .multipart(form)
-        .send()
-        .await?
-        .text()
-        .await?;
+    if !status.is_success() {
+        let message = if profile.error_message.is_empty() {
+            error_message_from_body(&res)
+        } else {
+            sxcu::parse_template(&profile.error_message, &res, &res_headers)
+                .unwrap_or_else(|_| error_message_from_body(&res))
+        };
+        return Err(UploadError::from_status(status, message).into());
+    }
 
-    tracing::debug!("Image uploaded {}", res);
-    let response: UploadResponse = serde_json::from_str(&res).unwrap();
+    let response =
+        parse_response(profile, &res, &res_headers).map_err(|e| UploadError::Unparseable(format!("{e}: {res}")))?;
     copy(response.url.clone()).await;
 
+    let entry = HistoryEntry {
+        id: upload_history::next_id(),
+        url: response.url.clone(),
+        delete_url: response.delete.clone(),
+        metadata: response.metadata.clone(),
+        path: path.clone(),
+        timestamp: chrono::Utc::now().timestamp(),
+        size,
+        mime,
+        uploader: profile.name.clone(),
+    };
+    if let Err(e) = upload_history::append(&entry) {
+        tracing::error!("Failed saving upload to history {e:?}");
+    }
+
     if print {
         println!("Image uploaded {}", response.url);
         println!("Delete URL: {}", response.delete);
@@ -166,11 +694,69 @@ pub async fn upload_file(
         {
             notif = notif.image_path(&path.to_string_lossy());
         };
-        notif.show()?;
+        // the upload already worked, a failing notification must not make the queue upload it again
+        if let Err(e) = notif.show() {
+            tracing::error!("Failed showing the upload notification {e:?}");
+        }
     }
     Ok(response)
 }
 
+/// The ascella profile an upload without a deletion url can be deleted with
+pub fn delete_profile<'a>(entry: &HistoryEntry, config: &'a AscellaConfig) -> Option<&'a UploaderProfile> {
+    if entry.url.starts_with("file://") {
+        return None;
+    }
+    config
+        .profiles
+        .iter()
+        .find(|p| p.name == entry.uploader && p.url.is_empty() && !p.api_key.is_empty())
+}
+
+/// Deletes an upload with its deletion url, ascella uploads without one are deleted through the api of the profile
+/// they were uploaded with
+///
+/// Uploads the server doesn't have anymore are removed from the history as well
+pub async fn delete_upload(
+    entry: &HistoryEntry,
+    config: &AscellaConfig,
+    client: &reqwest::Client,
+) -> anyhow::Result<()> {
+    let request = if !entry.delete_url.is_empty() {
+        client.get(&entry.delete_url)
+    } else {
+        let profile =
+            delete_profile(entry, config).ok_or_else(|| anyhow!("No deletion url stored for {}", entry.url))?;
+        let id = entry.url.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+        client
+            .delete(format!("{}/files/{id}", profile.api_url))
+            .header("ascella-token", &profile.api_key)
+    };
+    let res = request.send().await?;
+    let status = res.status();
+    if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+        info!("{} was already deleted", entry.url);
+    } else if !status.is_success() {
+        let body = res.text().await.unwrap_or_default();
+        return Err(UploadError::from_status(status, error_message_from_body(&body)).into());
+    } else {
+        info!("Deleted {}", entry.url);
+    }
+    upload_history::remove(entry.id)?;
+    Ok(())
+}
+
+fn parse_response(profile: &UploaderProfile, body: &str, headers: &HeaderMap) -> anyhow::Result<UploadResponse> {
+    if profile.url.is_empty() {
+        return Ok(serde_json::from_str(body)?);
+    }
+    Ok(UploadResponse {
+        url: sxcu::parse_template(&profile.url, body, headers)?,
+        delete: sxcu::parse_template(&profile.deletion_url, body, headers)?,
+        metadata: String::new(),
+    })
+}
+
 fn headermap_from_hashmap<'a, I, S>(headers: I) -> HeaderMap
 where
     I: Iterator<Item = (S, S)> + 'a,
@@ -189,3 +775,25 @@ where
         .map(|(k, v)| (k.unwrap(), v.unwrap()))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_errors_from_status() {
+        let error = |status: u16| UploadError::from_status(StatusCode::from_u16(status).unwrap(), String::new());
+        assert!(matches!(error(401), UploadError::Unauthorized(_)));
+        assert!(matches!(error(403), UploadError::Unauthorized(_)));
+        assert!(matches!(error(429), UploadError::QuotaExceeded(_)));
+        assert!(matches!(error(507), UploadError::QuotaExceeded(_)));
+        assert!(matches!(error(413), UploadError::PayloadTooLarge(_)));
+        assert!(matches!(error(502), UploadError::Server(..)));
+        assert!(matches!(error(404), UploadError::Rejected(..)));
+        assert!(error(503).is_retryable());
+        assert!(!error(401).is_retryable());
+        assert!(!error(413).is_retryable());
+        assert!(UploadError::Network(String::new()).is_retryable());
+        assert!(!UploadError::Unparseable(String::new()).is_retryable());
+    }
+}
@@ -0,0 +1,311 @@
+//! Removes old captures from the output directory
+//!
+//! Only files Ascella recorded in the upload history are considered, so anything else in a shared directory like
+//! `~/Pictures` is left alone. Captures that were only saved are kept unless `include_saved` is set and files still
+//! waiting in the upload queue are never removed
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    ascella_config::AscellaConfig,
+    upload_history::{self, HistoryEntry},
+};
+
+/// Extensions of the files captures end up as
+const CAPTURE_EXTENSIONS: [&str; 9] = ["png", "jpg", "jpeg", "webp", "avif", "tiff", "gif", "mp4", "webm"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    /// 0 keeps captures forever
+    pub max_age_days: u32,
+    /// 0 has no size limit
+    pub max_total_mb: u64,
+    /// newest captures to keep, 0 has no limit
+    pub keep_count: usize,
+    /// remove captures as soon as they are uploaded, only failed uploads stay around
+    pub keep_only_failed: bool,
+    /// also remove captures that were saved instead of uploaded
+    pub include_saved: bool,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age_days: 30,
+            max_total_mb: 0,
+            keep_count: 0,
+            keep_only_failed: false,
+            include_saved: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Removal {
+    pub path: PathBuf,
+    pub size: u64,
+    pub reason: &'static str,
+}
+
+/// What a cleanup removed, or would remove on a dry run
+#[derive(Debug, Clone, Default)]
+pub struct CleanupReport {
+    pub removed: Vec<Removal>,
+    pub kept: usize,
+    pub dry_run: bool,
+}
+
+impl CleanupReport {
+    pub fn freed(&self) -> u64 {
+        self.removed.iter().map(|removal| removal.size).sum()
+    }
+}
+
+struct Capture {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    uploaded: bool,
+}
+
+/// Applies the retention policy of the config, `protected` files are kept no matter what
+pub fn cleanup(config: &AscellaConfig, protected: &[PathBuf], dry_run: bool) -> Result<CleanupReport> {
+    let captures = captures(
+        &upload_history::load()?,
+        &config.output_dir(),
+        config.retention.include_saved,
+    );
+    let report = select(captures, &config.retention, protected, SystemTime::now(), dry_run);
+
+    if !dry_run {
+        for removal in &report.removed {
+            if let Err(e) = fs::remove_file(&removal.path) {
+                tracing::error!("Failed removing {} {e:?}", removal.path.display());
+            }
+        }
+        if !report.removed.is_empty() {
+            info!(
+                "Cleanup removed {} captures and freed {} bytes",
+                report.removed.len(),
+                report.freed()
+            );
+        }
+    }
+    Ok(report)
+}
+
+/// Captures in the history that still exist in `dir`, uploads of files from elsewhere are never touched
+fn captures(history: &[HistoryEntry], dir: &Path, include_saved: bool) -> Vec<Capture> {
+    let mut seen = HashSet::new();
+    let mut captures = Vec::new();
+    for entry in history {
+        let uploaded = !entry.url.starts_with("file://");
+        let is_capture = entry
+            .path
+            .extension()
+            .is_some_and(|ext| CAPTURE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()));
+        if !entry.path.starts_with(dir) || !is_capture || !(uploaded || include_saved) {
+            continue;
+        }
+        // the same file can be uploaded more than once
+        if !seen.insert(entry.path.clone()) {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(&entry.path) else {
+            continue;
+        };
+        if metadata.is_file() {
+            captures.push(Capture {
+                path: entry.path.clone(),
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                uploaded,
+            });
+        }
+    }
+    captures
+}
+
+/// Picks what to remove, the count and size limits keep the newest captures
+fn select(
+    mut captures: Vec<Capture>,
+    config: &RetentionConfig,
+    protected: &[PathBuf],
+    now: SystemTime,
+    dry_run: bool,
+) -> CleanupReport {
+    captures.sort_by_key(|capture| std::cmp::Reverse(capture.modified));
+    let max_age = Duration::from_secs(config.max_age_days as u64 * 24 * 60 * 60);
+    let max_total = config.max_total_mb.saturating_mul(1024 * 1024);
+
+    let mut report = CleanupReport {
+        dry_run,
+        ..Default::default()
+    };
+    let mut kept_size: u64 = 0;
+    for capture in captures {
+        if protected.contains(&capture.path) {
+            report.kept += 1;
+            continue;
+        }
+        let age = now.duration_since(capture.modified).unwrap_or_default();
+        let reason = if config.keep_only_failed && capture.uploaded {
+            Some("uploaded")
+        } else if config.max_age_days > 0 && age > max_age {
+            Some("too old")
+        } else if config.keep_count > 0 && report.kept >= config.keep_count {
+            Some("over the keep count")
+        } else if config.max_total_mb > 0 && kept_size.saturating_add(capture.size) > max_total {
+            Some("over the size limit")
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => report.removed.push(Removal {
+                path: capture.path,
+                size: capture.size,
+                reason,
+            }),
+            None => {
+                report.kept += 1;
+                kept_size += capture.size;
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn capture(name: &str, days_old: u32, size: u64, uploaded: bool) -> Capture {
+        Capture {
+            path: PathBuf::from(name),
+            size,
+            modified: SystemTime::UNIX_EPOCH + DAY * (100 - days_old),
+            uploaded,
+        }
+    }
+
+    fn removed(captures: Vec<Capture>, config: &RetentionConfig, protected: &[PathBuf]) -> Vec<String> {
+        let now = SystemTime::UNIX_EPOCH + DAY * 100;
+        select(captures, config, protected, now, true)
+            .removed
+            .into_iter()
+            .map(|removal| format!("{} {}", removal.path.display(), removal.reason))
+            .collect()
+    }
+
+    #[test]
+    fn limits_keep_the_newest() {
+        let captures = || {
+            vec![
+                capture("old", 40, 1, true),
+                capture("c", 3, 1024 * 1024, true),
+                capture("a", 1, 1024 * 1024, true),
+                capture("b", 2, 1024 * 1024, true),
+            ]
+        };
+        let config = RetentionConfig::default();
+        assert_eq!(removed(captures(), &config, &[]), ["old too old"]);
+
+        let config = RetentionConfig {
+            max_age_days: 0,
+            keep_count: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            removed(captures(), &config, &[]),
+            ["c over the keep count", "old over the keep count"]
+        );
+
+        let config = RetentionConfig {
+            max_age_days: 0,
+            max_total_mb: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            removed(captures(), &config, &[]),
+            ["c over the size limit", "old over the size limit"]
+        );
+
+        let config = RetentionConfig {
+            max_total_mb: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(removed(captures(), &config, &[]), ["old too old"]);
+    }
+
+    #[test]
+    fn protected_and_failed_captures_are_kept() {
+        let config = RetentionConfig {
+            keep_only_failed: true,
+            ..Default::default()
+        };
+        let captures = vec![
+            capture("uploaded", 1, 1, true),
+            capture("saved", 1, 1, false),
+            capture("queued", 50, 1, true),
+        ];
+        assert_eq!(
+            removed(captures, &config, &[PathBuf::from("queued")]),
+            ["uploaded uploaded"]
+        );
+    }
+
+    #[test]
+    fn only_history_captures_in_the_output_directory() {
+        let dir = std::env::temp_dir().join(format!("ascella-retention-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let entry = |name: &str, url: &str| {
+            let path = dir.join(name);
+            fs::write(&path, "data").ok();
+            HistoryEntry {
+                url: url.to_string(),
+                path,
+                ..Default::default()
+            }
+        };
+        let outside = std::env::temp_dir().join("ascella-retention-outside.png");
+        let history = vec![
+            entry("uploaded.png", "https://example.com/a"),
+            entry("uploaded.png", "https://example.com/b"),
+            entry("saved.png", "file:///saved.png"),
+            entry("notes.txt", "https://example.com/c"),
+            HistoryEntry {
+                url: "https://example.com/d".to_string(),
+                path: outside,
+                ..Default::default()
+            },
+            HistoryEntry {
+                url: "https://example.com/e".to_string(),
+                path: dir.join("deleted.png"),
+                ..Default::default()
+            },
+        ];
+        let names = |include_saved| -> Vec<String> {
+            captures(&history, &dir, include_saved)
+                .iter()
+                .map(|capture| capture.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+        assert_eq!(names(false), ["uploaded.png"]);
+        assert_eq!(names(true), ["uploaded.png", "saved.png"]);
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,12 +1,20 @@
-use std::time::Duration;
+use std::{cmp::Reverse, time::Duration};
 
 use anyhow::Result;
+use chrono::TimeZone;
 use eframe::egui::{self, Ui};
-
-use reqwest::{header::HeaderValue, Method};
+use egui_extras::{Column, TableBuilder};
 use serde::{Deserialize, Serialize};
 
-use crate::{ui::MyApp, Request, RequestType};
+use crate::{
+    request_handler::delete_profile,
+    ui::MyApp,
+    upload_history::{self, HistoryEntry},
+    utils::format_bytes,
+    Request, RequestType,
+};
+
+const PAGE_SIZE: usize = 15;
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct AscellaFile {
@@ -15,48 +23,232 @@ pub struct AscellaFile {
     raw: String,
 }
 
-pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()> {
-    ui.heading("History");
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub enum HistorySort {
+    #[default]
+    Newest,
+    Oldest,
+    Largest,
+    Smallest,
+}
 
-    for file in &app.history {
-        ui.horizontal(|ui| {
-            ui.label(&file.name);
-            ui.hyperlink(format!("https://picup.click/v/{}", file.vanity));
-            // if ui.hyperlink(format!("https://ascella.host/v/{}".file.vanity)) {
-            // open_url
-            // }
+impl HistorySort {
+    const ALL: [HistorySort; 4] = [Self::Newest, Self::Oldest, Self::Largest, Self::Smallest];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Newest => "Newest",
+            Self::Oldest => "Oldest",
+            Self::Largest => "Largest",
+            Self::Smallest => "Smallest",
+        }
+    }
+
+    fn sort(&self, files: &mut [&HistoryEntry]) {
+        match self {
+            Self::Newest => files.sort_by_key(|file| Reverse(file.timestamp)),
+            Self::Oldest => files.sort_by_key(|file| file.timestamp),
+            Self::Largest => files.sort_by_key(|file| Reverse(file.size)),
+            Self::Smallest => files.sort_by_key(|file| file.size),
+        }
+    }
+}
+
+fn matches_search(entry: &HistoryEntry, search: &str) -> bool {
+    search.is_empty()
+        || entry.url.to_lowercase().contains(search)
+        || entry.path.to_string_lossy().to_lowercase().contains(search)
+        || entry.mime.to_lowercase().contains(search)
+        || entry.uploader.to_lowercase().contains(search)
+}
+
+fn confirm_delete(app: &mut MyApp, ctx: &egui::Context) {
+    let Some(entry) = &app.pending_delete else {
+        return;
+    };
+    let mut confirmed = false;
+    let mut cancelled = false;
+    egui::Window::new("Delete upload")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            ui.label(format!("Delete {} from the server?", entry.url));
+            ui.small("This can't be undone");
+            ui.horizontal(|ui| {
+                confirmed = ui.button("Delete").clicked();
+                cancelled = ui.button("Cancel").clicked();
+            });
         });
+    if confirmed {
+        if let Some(entry) = app.pending_delete.take() {
+            app.sender.send(Request::DeleteUpload(entry)).ok();
+        }
+    } else if cancelled {
+        app.pending_delete = None;
+    }
+}
+
+pub fn screen(app: &mut MyApp, ui: &mut Ui, ctx: &egui::Context) -> Result<()> {
+    ui.heading("History");
+
+    confirm_delete(app, ctx);
+
+    ui.horizontal(|ui| {
+        let search_label = ui.label("Search ");
+        if ui
+            .text_edit_singleline(&mut app.history_search)
+            .labelled_by(search_label.id)
+            .changed()
+        {
+            app.history_page = 0;
+        }
+        egui::ComboBox::from_id_source("history_sort")
+            .selected_text(app.history_sort.name())
+            .width(90.0)
+            .show_ui(ui, |ui| {
+                for sort in HistorySort::ALL {
+                    ui.selectable_value(&mut app.history_sort, sort, sort.name());
+                }
+            });
+        if ui.button("Reload").clicked() {
+            app.local_history = upload_history::load().unwrap_or_default();
+        }
+    });
+
+    let search = app.history_search.to_lowercase();
+    let mut files: Vec<&HistoryEntry> = app
+        .local_history
+        .iter()
+        .filter(|entry| matches_search(entry, &search))
+        .collect();
+    app.history_sort.sort(&mut files);
+
+    let pages = files.len().div_ceil(PAGE_SIZE).max(1);
+    app.history_page = app.history_page.min(pages - 1);
+
+    let mut delete = None;
+    if files.is_empty() {
+        ui.small("Nothing uploaded yet");
+    } else {
+        TableBuilder::new(ui)
+            .striped(true)
+            .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+            .column(Column::initial(110.0))
+            .column(Column::initial(60.0))
+            .column(Column::remainder())
+            .column(Column::auto())
+            .min_scrolled_height(0.0)
+            .header(20.0, |mut header| {
+                header.col(|ui| {
+                    ui.strong("Date");
+                });
+                header.col(|ui| {
+                    ui.strong("Size");
+                });
+                header.col(|ui| {
+                    ui.strong("Url");
+                });
+                header.col(|_| {});
+            })
+            .body(|mut body| {
+                for entry in files.iter().skip(app.history_page * PAGE_SIZE).take(PAGE_SIZE) {
+                    body.row(18.0, |mut row| {
+                        row.col(|ui| {
+                            let date = chrono::Local
+                                .timestamp_opt(entry.timestamp, 0)
+                                .single()
+                                .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+                                .unwrap_or_default();
+                            ui.label(date);
+                        });
+                        row.col(|ui| {
+                            ui.label(format_bytes(entry.size));
+                        });
+                        row.col(|ui| {
+                            ui.hyperlink(&entry.url).on_hover_text(entry.path.to_string_lossy());
+                        });
+                        row.col(|ui| {
+                            if ui
+                                .add_enabled(
+                                    !entry.delete_url.is_empty() || delete_profile(entry, &app.config).is_some(),
+                                    egui::Button::new("Delete").small(),
+                                )
+                                .on_disabled_hover_text("No deletion url was returned for this upload")
+                                .clicked()
+                            {
+                                delete = Some((*entry).clone());
+                            }
+                        });
+                    });
+                }
+            });
+    }
+
+    if delete.is_some() {
+        app.pending_delete = delete;
     }
 
     ui.horizontal(|ui| {
-        if ui.button("Reset").clicked() {
-            app.history.clear();
-            app.history_index = 0;
-        }
-
-        if ui.button("Load more").clicked() {
-            let mut req = reqwest::Request::new(
-                Method::GET,
-                format!("{}/me/files?page={}", app.config.api_url, app.history_index)
-                    .parse()
-                    .unwrap(),
-            );
-            req.headers_mut()
-                .append("ascella-token", HeaderValue::from_str(&app.config.api_key).unwrap());
-
-            app.sender
-                .send(Request::DoRequest {
-                    r_type: RequestType::RequestPage,
-                    request: req,
-                })
-                .ok();
-            app.history_index += 1;
-
-            app.toasts
-                .basic("Fetching images")
-                .set_duration(Some(Duration::from_millis(600)));
+        if ui
+            .add_enabled(app.history_page > 0, egui::Button::new("Previous"))
+            .clicked()
+        {
+            app.history_page -= 1;
+        }
+        ui.label(format!("Page {} of {pages}", app.history_page + 1));
+        if ui
+            .add_enabled(app.history_page + 1 < pages, egui::Button::new("Next"))
+            .clicked()
+        {
+            app.history_page += 1;
         }
     });
 
+    if app.config.profile().api_key.is_empty() {
+        return Ok(());
+    }
+
+    egui::CollapsingHeader::new("Server uploads").show(ui, |ui| {
+        for file in &app.history {
+            ui.horizontal(|ui| {
+                ui.label(&file.name);
+                ui.hyperlink(format!("https://picup.click/v/{}", file.vanity));
+            });
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Reset").clicked() {
+                app.history.clear();
+                app.history_index = 0;
+            }
+
+            if ui.button("Load more").clicked() {
+                match app
+                    .config
+                    .profile()
+                    .api_request(&format!("/me/files?page={}", app.history_index))
+                {
+                    Ok(request) => {
+                        app.sender
+                            .send(Request::DoRequest {
+                                r_type: RequestType::RequestPage,
+                                request,
+                            })
+                            .ok();
+                        app.history_index += 1;
+
+                        app.toasts
+                            .basic("Fetching images")
+                            .set_duration(Some(Duration::from_millis(600)));
+                    }
+                    Err(e) => {
+                        app.toasts.error(format!("Failed fetching images\n{e:#}"));
+                    }
+                }
+            }
+        });
+    });
+
     Ok(())
 }
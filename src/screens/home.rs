@@ -1,12 +1,17 @@
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
-use eframe::egui::{self, Frame, Margin, RichText, Ui};
+use chrono::TimeZone;
+use eframe::egui::{self, Button, Frame, Margin, RichText, Ui};
 use egui_extras::{Column, TableBuilder};
+use egui_file::FileDialog;
 
-use crate::{ui::MyApp, Request, SendScreenshot};
+use crate::{screens::profile_selector, ui::MyApp, upload_queue::JobState, Request, SendScreenshot};
 
 pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()> {
     ui.heading("Home");
-    if app.config.api_key.is_empty() {
+    ui.horizontal(|ui| profile_selector(app, ui));
+    if app.config.profile().api_key.is_empty() {
         ui.small("No access key set gallery will be local only!");
     }
     if let Some(data) = &app.user {
@@ -57,7 +62,7 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
                                 r_type: app.config.s_type.clone(),
                                 send: SendScreenshot::Area,
                                 config: app.config.clone(),
-                                print: false,
+                                delay: Duration::from_secs(app.capture_delay as u64),
                             })
                             .ok();
                     }
@@ -69,7 +74,7 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
                                 r_type: app.config.s_type.clone(),
                                 send: SendScreenshot::Window,
                                 config: app.config.clone(),
-                                print: false,
+                                delay: Duration::from_secs(app.capture_delay as u64),
                             })
                             .ok();
                     }
@@ -81,12 +86,140 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
                                 r_type: app.config.s_type.clone(),
                                 send: SendScreenshot::Screen,
                                 config: app.config.clone(),
-                                print: false,
+                                delay: Duration::from_secs(app.capture_delay as u64),
                             })
                             .ok();
                     }
                 });
             })
         });
+
+    ui.horizontal(|ui| {
+        ui.label("Delay");
+        ui.add(
+            egui::DragValue::new(&mut app.capture_delay)
+                .clamp_range(0..=60)
+                .suffix("s"),
+        );
+        if let Some(countdown) = &app.countdown {
+            let remaining = countdown.ends.saturating_duration_since(Instant::now());
+            ui.label(format!(
+                "Capturing the {} in {}s",
+                countdown.mode,
+                remaining.as_secs_f32().ceil()
+            ));
+            if ui.add(Button::new("Cancel").small()).clicked() {
+                countdown.cancel.cancel();
+                app.countdown = None;
+            }
+        }
+    });
+
+    ui.horizontal(|ui| match app.recording {
+        Some(started) => {
+            if ui
+                .button(format!("Stop recording ({}s)", started.elapsed().as_secs()))
+                .clicked()
+            {
+                app.sender.send(Request::StopRecording).ok();
+            }
+        }
+        None => {
+            for (label, area) in [("Record Area", true), ("Record Screen", false)] {
+                if ui.button(label).clicked() {
+                    app.sender
+                        .send(Request::StartRecording {
+                            area,
+                            config: app.config.clone(),
+                        })
+                        .ok();
+                }
+            }
+        }
+    });
+
+    ui.horizontal(|ui| {
+        if ui
+            .button("Upload file")
+            .on_hover_text("Drop files onto the window to upload several at once")
+            .clicked()
+        {
+            let mut dialog = FileDialog::open_file(app.opened_file.clone())
+                .resizable(false)
+                .show_rename(false);
+            dialog.open();
+            app.upload_file_dialog = Some(dialog);
+        }
+        if ui.button("Upload clipboard").clicked() {
+            app.sender.send(Request::UploadClipboard(app.config.clone())).ok();
+        }
+    });
+
+    if let Some(status) = &app.pipeline_stage {
+        ui.horizontal(|ui| {
+            ui.spinner();
+            ui.label(format!(
+                "{} {} ({:.1}s)",
+                status.stage,
+                status.file,
+                status.started.elapsed().as_secs_f32()
+            ));
+            if let Some(cancel) = &status.cancel {
+                if ui
+                    .add_enabled(!cancel.is_cancelled(), Button::new("Cancel").small())
+                    .clicked()
+                {
+                    cancel.cancel();
+                }
+            }
+        });
+    }
+
+    for progress in app.upload_progress.values() {
+        ui.add(
+            egui::ProgressBar::new(progress.fraction())
+                .text(progress.to_string())
+                .animate(true),
+        );
+    }
+
+    if !app.upload_jobs.is_empty() {
+        egui::CollapsingHeader::new(format!("Uploads ({})", app.upload_jobs.len()))
+            .default_open(true)
+            .show(ui, |ui| {
+                for job in &app.upload_jobs {
+                    ui.horizontal(|ui| {
+                        ui.label(job.path.file_name().unwrap_or_default().to_string_lossy());
+                        match &job.state {
+                            JobState::Pending if job.attempts == 0 => {
+                                ui.small("uploading");
+                            }
+                            JobState::Pending => {
+                                let next = chrono::Local
+                                    .timestamp_millis_opt(job.next_attempt)
+                                    .single()
+                                    .map(|date| date.format("%H:%M:%S").to_string())
+                                    .unwrap_or_default();
+                                ui.small(format!("attempt {} failed, retrying at {next}", job.attempts));
+                            }
+                            JobState::Failed(e) => {
+                                ui.small(format!("failed: {e}"));
+                            }
+                        }
+                        if ui.small_button("Retry").clicked() {
+                            app.sender
+                                .send(Request::RetryUpload {
+                                    id: job.id,
+                                    config: app.config.clone(),
+                                })
+                                .ok();
+                        }
+                        if ui.small_button("Cancel").clicked() {
+                            app.sender.send(Request::CancelUpload(job.id)).ok();
+                        }
+                    });
+                }
+            });
+    }
     Ok(())
 }
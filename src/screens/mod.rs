@@ -1,3 +1,26 @@
+use eframe::egui::{self, Ui};
+
+use crate::ui::MyApp;
+
 pub mod history;
 pub mod home;
 pub mod settings;
+
+/// Combobox to switch the profile uploads go to
+pub fn profile_selector(app: &mut MyApp, ui: &mut Ui) {
+    let profile_label = ui.label("Profile ");
+    let before = app.config.default_profile.clone();
+    egui::ComboBox::from_id_source(profile_label.id)
+        .selected_text(&app.config.default_profile)
+        .width(120.0)
+        .show_ui(ui, |ui| {
+            for profile in &app.config.profiles {
+                ui.selectable_value(&mut app.config.default_profile, profile.name.clone(), &profile.name);
+            }
+        });
+    if before != app.config.default_profile {
+        // user info belongs to the api key of the old profile
+        app.user = None;
+        app.retrieving_user = false;
+    }
+}
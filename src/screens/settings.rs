@@ -1,9 +1,24 @@
+use std::collections::BTreeMap;
+
 use anyhow::Result;
 use eframe::egui::{self, RichText, Ui};
 use egui_extras::{Column, TableBuilder};
 use egui_file::FileDialog;
+use regex::Regex;
 
-use crate::{ui::MyApp, utils::theme_to_name, Request, ScreenshotType};
+use crate::{
+    filename,
+    hotkeys::{Hotkey, HotkeyAction},
+    pipeline::{ImageFormat, OptimizePreset, Processor},
+    recording::{RecorderType, RecordingFormat},
+    redaction::{RedactionPreset, RedactionRule},
+    screens::profile_selector,
+    sxcu::BodyType,
+    ui::MyApp,
+    utils::{format_bytes, theme_to_name},
+    watermark::WatermarkPosition,
+    Request, ScreenshotType,
+};
 
 pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()> {
     ui.heading("Settings");
@@ -13,40 +28,115 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
             .resizable(false)
             .show_rename(false)
             .filter(Box::new(|f| {
-                f.extension().map_or(false, |ext| ext == "json" || ext == "sxcu")
+                f.extension().is_some_and(|ext| ext == "json" || ext == "sxcu")
             }));
         dialog.open();
         app.open_file_dialog = Some(dialog);
     }
+    ui.horizontal(|ui| {
+        profile_selector(app, ui);
+        if ui.button("New").clicked() {
+            app.config.add_profile();
+        }
+        if ui
+            .add_enabled(app.config.profiles.len() > 1, egui::Button::new("Remove"))
+            .clicked()
+        {
+            app.config.remove_profile();
+            app.user = None;
+            app.retrieving_user = false;
+        }
+    });
+    ui.horizontal(|ui| {
+        let name_label = ui.label("Profile name ");
+        // a name that can't be used yet stays in the field without renaming the profile
+        let pending = egui::Id::new("pending_profile_name").with(&app.config.default_profile);
+        let mut name = ui
+            .data_mut(|data| data.get_temp::<String>(pending))
+            .unwrap_or_else(|| app.config.default_profile.clone());
+        let error = app.config.check_profile_name(&name).err();
+        let response = ui
+            .add(egui::TextEdit::singleline(&mut name).text_color_opt(error.is_some().then_some(egui::Color32::RED)))
+            .labelled_by(name_label.id);
+        if response.changed() {
+            ui.data_mut(|data| data.remove::<String>(pending));
+            if app.config.rename_profile(&name).is_err() {
+                ui.data_mut(|data| data.insert_temp(pending, name));
+            }
+        }
+        if let Some(e) = error {
+            ui.small(e.to_string());
+        }
+    });
     ui.horizontal(|ui| {
         let token_label = ui.label("Ascella Token (Optional) ");
-        ui.text_edit_singleline(&mut app.config.api_key)
+        ui.text_edit_singleline(&mut app.config.profile_mut().api_key)
             .labelled_by(token_label.id);
     });
+    ui.checkbox(
+        &mut app.config.profile_mut().annotate,
+        "Annotate screenshots before uploading",
+    );
+    ui.checkbox(
+        &mut app.config.profile_mut().local_only,
+        "Save captures with this profile instead of uploading",
+    );
+    ui.horizontal(|ui| {
+        let output = &mut app.config.profile_mut().output;
+        let format_label = ui.label("Upload as ");
+        egui::ComboBox::from_id_source(format_label.id)
+            .selected_text(output.format.name())
+            .width(120.0)
+            .show_ui(ui, |ui| {
+                for format in ImageFormat::ALL {
+                    ui.selectable_value(&mut output.format, format, format.name());
+                }
+            });
+        if output.format.is_lossy() {
+            ui.add(egui::Slider::new(&mut output.quality, 1..=100).text("Quality"));
+        }
+        ui.label("Max size");
+        ui.add(egui::DragValue::new(&mut output.max_dimension).suffix("px"))
+            .on_hover_text("Longest side of the image, 0 keeps the original size");
+    });
     ui.horizontal(|ui| {
         let screenshot_label = ui.label("Screenshot tool ");
         egui::ComboBox::from_id_source(screenshot_label.id)
             .selected_text(app.config.s_type.name())
             .width(120.0)
             .show_ui(ui, |ui| {
-                ui.selectable_value(&mut app.config.s_type, ScreenshotType::Flameshot, "Flameshot");
-                #[cfg(target_os = "macos")]
-                ui.selectable_value(&mut app.config.s_type, ScreenshotType::Screencapture, "Screencapture");
-                #[cfg(target_os = "linux")]
-                ui.selectable_value(&mut app.config.s_type, ScreenshotType::Scrot, "Scrot");
-                #[cfg(target_os = "linux")]
-                ui.selectable_value(&mut app.config.s_type, ScreenshotType::Spectacle, "Spectacle");
+                for tool in ScreenshotType::BUILTIN.into_iter().filter(|tool| tool.supported()) {
+                    let label = if tool.installed() {
+                        tool.name().to_string()
+                    } else {
+                        format!("{} (not installed)", tool.name())
+                    };
+                    ui.selectable_value(&mut app.config.s_type, tool, label);
+                }
                 ui.selectable_value(
                     &mut app.config.s_type,
                     ScreenshotType::Custom {
                         area: String::new(),
                         screen: String::new(),
                         window: String::new(),
+                        shell: false,
+                        stdout: false,
+                        env: BTreeMap::new(),
                     },
                     "Custom",
                 );
-            })
-            .response
+            });
+        if ui.small_button("Detect").clicked() {
+            match ScreenshotType::detect().into_iter().next() {
+                Some(tool) => {
+                    app.toasts.info(format!("Using {}", tool.name()));
+                    app.config.s_type = tool;
+                }
+                None => {
+                    app.toasts.error("No supported screenshot tool found on the PATH");
+                }
+            }
+        }
     });
 
     ui.horizontal(|ui| {
@@ -67,8 +157,21 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
             .response
     });
 
-    if let ScreenshotType::Custom { area, screen, window } = &mut app.config.s_type {
+    if let ScreenshotType::Custom {
+        area,
+        screen,
+        window,
+        shell,
+        stdout,
+        env,
+    } = &mut app.config.s_type
+    {
         ui.heading("Custom Config use {file} to set the output dir");
+        ui.small("Arguments are split like a shell would, quote paths with spaces");
+        ui.checkbox(shell, "Run through sh -c")
+            .on_hover_text("Allows pipes and $(...), {file} is quoted for you");
+        ui.checkbox(stdout, "Read the image from stdout")
+            .on_hover_text("For tools that print the image instead of saving it to {file}");
 
         ui.horizontal(|ui| {
             let token_label = ui.label("Area command ");
@@ -82,16 +185,118 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
             let token_label = ui.label("Window command ");
             ui.text_edit_singleline(window).labelled_by(token_label.id);
         });
+
+        ui.heading(RichText::new("Environment").size(15.0));
+        let mut removed = None;
+        for (name, value) in env.iter_mut() {
+            ui.horizontal(|ui| {
+                ui.label(name);
+                ui.text_edit_singleline(value);
+                if ui.small_button("Remove").clicked() {
+                    removed = Some(name.clone());
+                }
+            });
+        }
+        if let Some(name) = removed {
+            env.remove(&name);
+        }
+        ui.horizontal(|ui| {
+            let id = ui.id().with("new_env_var");
+            let mut name = ui.data_mut(|data| data.get_temp::<String>(id).unwrap_or_default());
+            ui.add(
+                egui::TextEdit::singleline(&mut name)
+                    .hint_text("NAME")
+                    .desired_width(120.0),
+            );
+            if ui.button("Add variable").clicked() && !name.trim().is_empty() {
+                env.insert(name.trim().to_string(), String::new());
+                name.clear();
+            }
+            ui.data_mut(|data| data.insert_temp(id, name));
+        });
     }
+    ui.horizontal(|ui| {
+        let dir_label = ui.label("Save captures to ");
+        ui.add(egui::TextEdit::singleline(&mut app.config.output_dir).hint_text("~/.ascella/images"))
+            .labelled_by(dir_label.id);
+    });
+    ui.horizontal(|ui| {
+        let name_label = ui.label("File name ");
+        ui.text_edit_singleline(&mut app.config.filename_template)
+            .labelled_by(name_label.id)
+            .on_hover_text(template_help());
+    });
     ui.horizontal(|ui| ui.checkbox(&mut app.config.notifications_enabled, "Notifications Enabled"));
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut app.config.strip_metadata, "Strip metadata from uploads")
+            .on_hover_text("Removes EXIF (camera, GPS), XMP and IPTC data from JPEG, PNG, WebP and TIFF images")
+    });
+
+    egui::CollapsingHeader::new("Saving").show(ui, |ui| {
+        ui.checkbox(&mut app.config.local_only, "Never upload captures, only save them");
+        ui.small("Saved captures use the directory and file name above");
+        ui.checkbox(&mut app.config.local_save.copy_image, "Copy the image to the clipboard");
+    });
+    egui::CollapsingHeader::new("Redaction").show(ui, |ui| redaction_settings(app, ui));
+    egui::CollapsingHeader::new("Recording").show(ui, |ui| recording_settings(app, ui));
+    egui::CollapsingHeader::new("Cleanup").show(ui, |ui| cleanup_settings(app, ui));
+    egui::CollapsingHeader::new("Hotkeys").show(ui, |ui| hotkey_settings(app, ui));
 
     egui::CollapsingHeader::new("Advanced").show(ui, |ui| {
         ui.horizontal(|ui| {
             let url_label = ui.label("Ascella API URL ");
-            ui.text_edit_singleline(&mut app.config.api_url)
+            ui.text_edit_singleline(&mut app.config.profile_mut().api_url)
                 .labelled_by(url_label.id);
         });
 
+        let profile = app.config.profile_mut();
+        ui.heading(RichText::new("Uploader").size(15.0));
+        ui.horizontal(|ui| {
+            let method_label = ui.label("Request ");
+            egui::ComboBox::from_id_source(method_label.id)
+                .selected_text(&profile.request_method)
+                .width(70.0)
+                .show_ui(ui, |ui| {
+                    for method in ["POST", "PUT", "PATCH", "GET"] {
+                        ui.selectable_value(&mut profile.request_method, method.to_string(), method);
+                    }
+                });
+            ui.text_edit_singleline(&mut profile.request_url);
+        });
+        ui.horizontal(|ui| {
+            let body_label = ui.label("Body ");
+            egui::ComboBox::from_id_source(body_label.id)
+                .selected_text(profile.body.name())
+                .width(120.0)
+                .show_ui(ui, |ui| {
+                    for body in BodyType::ALL {
+                        ui.selectable_value(&mut profile.body, body, body.name());
+                    }
+                });
+        });
+        if profile.body == BodyType::MultipartFormData {
+            ui.horizontal(|ui| {
+                let form_label = ui.label("File form name ");
+                ui.text_edit_singleline(&mut profile.file_form_name)
+                    .labelled_by(form_label.id);
+            });
+        }
+        ui.small("Leave URL empty for Ascella, otherwise use {json:path}, {regex:pattern|group} or {header:name}");
+        ui.horizontal(|ui| {
+            let url_label = ui.label("URL ");
+            ui.text_edit_singleline(&mut profile.url).labelled_by(url_label.id);
+        });
+        ui.horizontal(|ui| {
+            let url_label = ui.label("Deletion URL ");
+            ui.text_edit_singleline(&mut profile.deletion_url)
+                .labelled_by(url_label.id);
+        });
+        ui.horizontal(|ui| {
+            let error_label = ui.label("Error message ");
+            ui.text_edit_singleline(&mut profile.error_message)
+                .labelled_by(error_label.id);
+        });
+
 
         ui.horizontal(|ui| ui.checkbox(&mut app.config.debug, "Debug Mode"));
         ui.heading(RichText::new("Headers").size(15.0));
@@ -103,7 +308,7 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
             .column(Column::remainder())
             .min_scrolled_height(0.0)
             .body(|mut body| {
-                for (name, value) in &mut app.config.headers.iter_mut() {
+                for (name, value) in &mut app.config.profile_mut().headers.iter_mut() {
                     body.row(24.0, |mut row| {
                         row.col(|ui| {
                             ui.label(name);
@@ -114,14 +319,12 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
                     });
                 }
             });
-        ui.heading(RichText::new("PNG Optimizations").size(15.0));
-        ui.horizontal(|ui| ui.checkbox(&mut app.config.optimize_png, "Enabled"));
-        ui.horizontal(|ui| {
-            let url_label = ui.label("Timeout (ms) ");
-            ui.add(egui::DragValue::new(&mut app.config.optimize_timeout))
-                .labelled_by(url_label.id);
-        });
-        ui.label("Want to save me some storage space or are you uploading big images turn this on, it will make uploading a fair bit slower though!")
+        ui.heading(RichText::new("Watermark").size(15.0));
+        watermark_settings(app, ui);
+        ui.heading(RichText::new("Processing").size(15.0));
+        ui.small("Applied to screenshots in this order before they are uploaded");
+        pipeline_settings(app, ui);
+        ui.label("Want to save me some storage space or are you uploading big images add Optimize PNG, it will make uploading a fair bit slower though!")
     });
 
     if ui.button("save").clicked() {
@@ -129,3 +332,328 @@ pub fn screen(app: &mut MyApp, ui: &mut Ui, _ctx: &egui::Context) -> Result<()>
     }
     Ok(())
 }
+
+fn template_help() -> String {
+    let mut help = "strftime format like %Y-%m/%d_%H-%M-%S, / creates folders".to_string();
+    for (token, description) in filename::TOKENS {
+        help.push_str(&format!("\n{token} {description}"));
+    }
+    help
+}
+
+fn recording_settings(app: &mut MyApp, ui: &mut Ui) {
+    let recording = &mut app.config.recording;
+    ui.horizontal(|ui| {
+        let recorder_label = ui.label("Recorder ");
+        egui::ComboBox::from_id_source(recorder_label.id)
+            .selected_text(recording.recorder.name())
+            .width(120.0)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut recording.recorder, RecorderType::WfRecorder, "wf-recorder");
+                ui.selectable_value(&mut recording.recorder, RecorderType::Ffmpeg, "ffmpeg");
+                ui.selectable_value(
+                    &mut recording.recorder,
+                    RecorderType::Custom {
+                        area: String::new(),
+                        screen: String::new(),
+                    },
+                    "Custom",
+                );
+            });
+        ui.label("FPS");
+        ui.add(egui::DragValue::new(&mut recording.fps).clamp_range(1..=120));
+    });
+    if let RecorderType::Custom { area, screen } = &mut recording.recorder {
+        ui.small("Use {file} for the output, the recorder is stopped with SIGINT");
+        ui.horizontal(|ui| {
+            let area_label = ui.label("Area command ");
+            ui.text_edit_singleline(area).labelled_by(area_label.id);
+        });
+        ui.horizontal(|ui| {
+            let screen_label = ui.label("Screen command ");
+            ui.text_edit_singleline(screen).labelled_by(screen_label.id);
+        });
+    }
+    ui.horizontal(|ui| {
+        let format_label = ui.label("Upload as ");
+        egui::ComboBox::from_id_source(format_label.id)
+            .selected_text(recording.format.name())
+            .width(70.0)
+            .show_ui(ui, |ui| {
+                for format in RecordingFormat::ALL {
+                    ui.selectable_value(&mut recording.format, format, format.name());
+                }
+            });
+        if recording.format == RecordingFormat::Gif {
+            ui.label("GIF FPS");
+            ui.add(egui::DragValue::new(&mut recording.gif_fps).clamp_range(1..=50));
+        }
+    });
+    if recording.format != RecordingFormat::Mp4 {
+        ui.small("Converting needs ffmpeg");
+    }
+}
+
+fn hotkey_settings(app: &mut MyApp, ui: &mut Ui) {
+    let hotkeys = &mut app.config.hotkeys;
+    ui.checkbox(&mut hotkeys.enabled, "Enable global hotkeys while Ascella is running");
+    for action in HotkeyAction::ALL {
+        ui.horizontal(|ui| {
+            let binding = hotkeys.binding_mut(action);
+            let valid = binding.trim().is_empty() || Hotkey::parse(binding).is_ok();
+            ui.add(
+                egui::TextEdit::singleline(binding)
+                    .hint_text("not bound")
+                    .desired_width(120.0)
+                    .text_color_opt((!valid).then_some(egui::Color32::RED)),
+            )
+            .on_hover_text("Ctrl, Alt, Shift and Super with a letter, digit, F1-F24, Print, Space or Escape");
+            ui.label(action.description());
+        });
+    }
+    ui.small("Applied when saving, on Wayland the compositor asks to confirm the keys and may pick different ones");
+}
+
+fn cleanup_settings(app: &mut MyApp, ui: &mut Ui) {
+    let retention = &mut app.config.retention;
+    ui.checkbox(&mut retention.enabled, "Remove old captures automatically")
+        .on_hover_text("Checked on startup and every hour, files waiting to be uploaded are never removed");
+    ui.horizontal(|ui| {
+        ui.label("Max age");
+        ui.add(egui::DragValue::new(&mut retention.max_age_days).suffix(" days"));
+        ui.label("Max total size");
+        ui.add(egui::DragValue::new(&mut retention.max_total_mb).suffix(" MB"));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Keep newest");
+        ui.add(egui::DragValue::new(&mut retention.keep_count));
+        ui.small("0 turns a limit off");
+    });
+    ui.checkbox(
+        &mut retention.keep_only_failed,
+        "Only keep captures that failed to upload",
+    );
+    ui.checkbox(&mut retention.include_saved, "Also remove saved captures")
+        .on_hover_text("Captures that were saved instead of uploaded are kept otherwise");
+    ui.small("Only applies to captures in the capture directory that are in the upload history");
+
+    ui.horizontal(|ui| {
+        for (label, dry_run) in [("Preview", true), ("Clean up now", false)] {
+            if ui.button(label).clicked() {
+                app.sender
+                    .send(Request::Cleanup {
+                        config: app.config.clone(),
+                        dry_run,
+                    })
+                    .ok();
+            }
+        }
+    });
+    if let Some(report) = &app.cleanup_report {
+        ui.label(format!(
+            "{} {} captures ({}), keeping {}",
+            if report.dry_run { "Would remove" } else { "Removed" },
+            report.removed.len(),
+            format_bytes(report.freed()),
+            report.kept
+        ));
+        egui::ScrollArea::vertical()
+            .id_source("cleanup_report")
+            .max_height(150.0)
+            .show(ui, |ui| {
+                for removal in &report.removed {
+                    ui.small(format!(
+                        "{} ({}, {})",
+                        removal.path.display(),
+                        format_bytes(removal.size),
+                        removal.reason
+                    ));
+                }
+            });
+    }
+}
+
+fn redaction_settings(app: &mut MyApp, ui: &mut Ui) {
+    let redaction = &mut app.config.redaction;
+    ui.checkbox(&mut redaction.enabled, "Redact screenshots before uploading");
+    ui.horizontal(|ui| {
+        let ocr_label = ui.label("OCR command ");
+        ui.text_edit_singleline(&mut redaction.ocr_command)
+            .labelled_by(ocr_label.id)
+            .on_hover_text("{file} is replaced with the screenshot, has to print tesseract style TSV");
+    });
+
+    ui.heading(RichText::new("Areas").size(15.0));
+    let mut removed = None;
+    for (i, preset) in redaction.presets.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut preset.enabled, "");
+            ui.add(egui::TextEdit::singleline(&mut preset.name).desired_width(80.0));
+            ui.label("x");
+            ui.add(egui::DragValue::new(&mut preset.region.x));
+            ui.label("y");
+            ui.add(egui::DragValue::new(&mut preset.region.y));
+            ui.label("w");
+            ui.add(egui::DragValue::new(&mut preset.region.width));
+            ui.label("h");
+            ui.add(egui::DragValue::new(&mut preset.region.height));
+            if ui.small_button("Remove").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+    if let Some(i) = removed {
+        redaction.presets.remove(i);
+    }
+    if ui.button("Add area").clicked() {
+        redaction.presets.push(RedactionPreset::default());
+    }
+
+    ui.heading(RichText::new("Text rules").size(15.0));
+    let mut removed = None;
+    for (i, rule) in redaction.rules.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut rule.enabled, "");
+            ui.add(egui::TextEdit::singleline(&mut rule.name).desired_width(80.0));
+            let valid = Regex::new(&rule.pattern).is_ok();
+            ui.add(
+                egui::TextEdit::singleline(&mut rule.pattern)
+                    .hint_text("regex")
+                    .text_color_opt((!valid).then_some(egui::Color32::RED)),
+            );
+            if ui.small_button("Remove").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+    if let Some(i) = removed {
+        redaction.rules.remove(i);
+    }
+    if ui.button("Add rule").clicked() {
+        redaction.rules.push(RedactionRule::default());
+    }
+
+    if ui.button("Preview on image").clicked() {
+        let mut dialog = FileDialog::open_file(app.opened_file.clone())
+            .resizable(false)
+            .show_rename(false);
+        dialog.open();
+        app.redaction_file_dialog = Some(dialog);
+    }
+}
+
+fn pipeline_settings(app: &mut MyApp, ui: &mut Ui) {
+    let pipeline = &mut app.config.pipeline;
+    let mut moved = None;
+    let mut removed = None;
+    let len = pipeline.len();
+    for (i, stage) in pipeline.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(format!("{}. {}", i + 1, stage.name()));
+            match stage {
+                Processor::Optimize { timeout_ms, preset } => {
+                    egui::ComboBox::from_id_source(("optimize_preset", i))
+                        .selected_text(preset.name())
+                        .width(70.0)
+                        .show_ui(ui, |ui| {
+                            for p in OptimizePreset::ALL {
+                                ui.selectable_value(preset, p, p.name());
+                            }
+                        });
+                    ui.label("Timeout (ms)");
+                    ui.add(egui::DragValue::new(timeout_ms));
+                }
+                Processor::Resize { max_width, max_height } => {
+                    ui.label("Max width");
+                    ui.add(egui::DragValue::new(max_width));
+                    ui.label("Max height");
+                    ui.add(egui::DragValue::new(max_height));
+                }
+                Processor::Convert { format, quality } => {
+                    egui::ComboBox::from_id_source(("convert_format", i))
+                        .selected_text(format.name())
+                        .width(70.0)
+                        .show_ui(ui, |ui| {
+                            for f in ImageFormat::ALL {
+                                ui.selectable_value(format, f, f.name());
+                            }
+                        });
+                    if format.is_lossy() {
+                        ui.add(egui::Slider::new(quality, 1..=100).text("Quality"));
+                    }
+                }
+                Processor::Watermark => {
+                    ui.small("uses the watermark settings");
+                }
+                Processor::StripMetadata => {}
+                Processor::Command { command } => {
+                    ui.text_edit_singleline(command)
+                        .on_hover_text("{file} is replaced with the image, edit the file in place");
+                }
+            }
+            if ui.add_enabled(i > 0, egui::Button::new("Up").small()).clicked() {
+                moved = Some((i, i - 1));
+            }
+            if ui.add_enabled(i + 1 < len, egui::Button::new("Down").small()).clicked() {
+                moved = Some((i, i + 1));
+            }
+            if ui.small_button("Remove").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+    if let Some((a, b)) = moved {
+        pipeline.swap(a, b);
+    }
+    if let Some(i) = removed {
+        pipeline.remove(i);
+    }
+    egui::ComboBox::from_id_source("add_stage")
+        .selected_text("Add stage")
+        .width(120.0)
+        .show_ui(ui, |ui| {
+            for stage in Processor::defaults() {
+                if ui.selectable_label(false, stage.name()).clicked() {
+                    pipeline.push(stage);
+                }
+            }
+        });
+}
+
+fn watermark_settings(app: &mut MyApp, ui: &mut Ui) {
+    let config = &mut app.config;
+    let mut enabled = config.pipeline.contains(&Processor::Watermark);
+    if ui.checkbox(&mut enabled, "Add a watermark to screenshots").changed() {
+        if enabled {
+            config.pipeline.push(Processor::Watermark);
+        } else {
+            config.pipeline.retain(|stage| *stage != Processor::Watermark);
+        }
+    }
+
+    let watermark = &mut config.watermark;
+    ui.horizontal(|ui| {
+        let text_label = ui.label("Text ");
+        ui.text_edit_singleline(&mut watermark.text).labelled_by(text_label.id);
+    });
+    ui.horizontal(|ui| {
+        let image_label = ui.label("Image ");
+        ui.add(egui::TextEdit::singleline(&mut watermark.image).hint_text("path, used instead of the text"))
+            .labelled_by(image_label.id);
+    });
+    ui.horizontal(|ui| {
+        let position_label = ui.label("Position ");
+        egui::ComboBox::from_id_source(position_label.id)
+            .selected_text(watermark.position.name())
+            .width(110.0)
+            .show_ui(ui, |ui| {
+                for position in WatermarkPosition::ALL {
+                    ui.selectable_value(&mut watermark.position, position, position.name());
+                }
+            });
+        ui.label("Margin");
+        ui.add(egui::DragValue::new(&mut watermark.margin).suffix("px"));
+    });
+    ui.add(egui::Slider::new(&mut watermark.opacity, 0..=100).text("Opacity %"));
+    ui.add(egui::Slider::new(&mut watermark.scale, 1.0..=100.0).text("Width % of the screenshot"));
+}
@@ -1,6 +1,14 @@
+use std::{collections::BTreeMap, io::ErrorKind, path::PathBuf};
+
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::process::Command;
 
-use crate::{utils::ascella_dir, SendScreenshot};
+use crate::{
+    utils::{on_path, split_command},
+    SendScreenshot,
+};
 
 #[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(tag = "type")]
@@ -10,80 +18,362 @@ pub enum ScreenshotType {
     Spectacle,
     Scrot,
     Screencapture,
+    /// grim with slurp for areas, windows are looked up through Hyprland or Sway
+    Grim,
+    Maim,
+    GnomeScreenshot,
+    Xfce4Screenshooter,
+    /// ImageMagick
+    Import,
     Custom {
         area: String,
         screen: String,
         window: String,
+        /// run the commands through `sh -c` so pipes and substitutions work
+        #[serde(default)]
+        shell: bool,
+        /// the tool prints the image instead of writing it to `{file}`
+        #[serde(default)]
+        stdout: bool,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
     },
 }
 
+/// A screenshot command ready to run
+pub struct ScreenshotCommand {
+    /// where the screenshot ends up, the extension may change when it is read from stdout
+    pub path: PathBuf,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    /// the image is read from stdout instead of the file
+    pub stdout: bool,
+}
+
 impl ScreenshotType {
+    /// Every tool with a built in invocation, in the order `detect` prefers them
+    pub const BUILTIN: [ScreenshotType; 9] = [
+        Self::Grim,
+        Self::Flameshot,
+        Self::Spectacle,
+        Self::GnomeScreenshot,
+        Self::Xfce4Screenshooter,
+        Self::Maim,
+        Self::Scrot,
+        Self::Import,
+        Self::Screencapture,
+    ];
+
     pub fn name(&self) -> &'static str {
         match self {
             Self::Flameshot => "flameshot",
             Self::Spectacle => "spectacle",
             Self::Scrot => "scrot",
             Self::Screencapture => "screencapture",
+            Self::Grim => "grim",
+            Self::Maim => "maim",
+            Self::GnomeScreenshot => "gnome-screenshot",
+            Self::Xfce4Screenshooter => "xfce4-screenshooter",
+            Self::Import => "import",
             Self::Custom { .. } => "custom",
         }
     }
-    fn generate_file_path() -> String {
-        let filename = chrono::offset::Local::now().format("%Y-%m-%d_%H-%M-%S.png").to_string();
-        ascella_dir()
-            .join("images")
-            .join(filename)
-            .to_string_lossy()
-            .to_string()
-    }
-    fn area_command(&self) -> (String, String) {
-        let file = Self::generate_file_path();
-        (
-            file.clone(),
-            match self {
-                Self::Flameshot => format!("flameshot gui -c -p {}", file),
-                Self::Spectacle => format!("spectacle -rbno {}", file),
-                Self::Scrot => format!("scrot --select {}", file),
-                Self::Screencapture => format!("screencapture -S {}", file),
-                Self::Custom { area, .. } => area.replace("{file}", &file),
-            },
-        )
-    }
-    // saves to .ascella/images with a chrono filename
-    fn screen_command(&self) -> (String, String) {
-        let file = Self::generate_file_path();
-        (
-            file.clone(),
-            match self {
-                Self::Flameshot => format!("flameshot gui -p {}", file),
-                Self::Spectacle => format!("spectacle -fbno {}", file),
-                Self::Scrot => format!("scrot {}", file),
-                Self::Screencapture => format!("screencapture -S {}", file),
-                Self::Custom { screen, .. } => screen.replace("{file}", &file),
-            },
-        )
-    }
-    // saves to .ascella/images with a chrono filename
-    fn window_command(&self) -> (String, String) {
-        let file = Self::generate_file_path();
-        (
-            file.clone(),
-            match self {
-                Self::Flameshot => format!("flameshot gui -p {} -w", file),
-                Self::Spectacle => format!("spectacle -abno {}", file),
-                Self::Scrot => format!("scrot --border --focused {}", file),
-                Self::Screencapture => {
-                    format!("screencapture -w {}", file)
+
+    /// Whether the tool works on this os and session
+    pub fn supported(&self) -> bool {
+        match self {
+            Self::Flameshot | Self::Custom { .. } => true,
+            Self::Screencapture => cfg!(target_os = "macos"),
+            Self::Grim => cfg!(target_os = "linux") && std::env::var_os("WAYLAND_DISPLAY").is_some(),
+            _ => cfg!(target_os = "linux"),
+        }
+    }
+
+    /// Programs that have to be on the PATH for every kind of screenshot to work
+    fn programs(&self) -> &'static [&'static str] {
+        match self {
+            Self::Grim => &["grim", "slurp"],
+            Self::Maim => &["maim", "xdotool"],
+            Self::Import => &["import", "xdotool"],
+            Self::Flameshot => &["flameshot"],
+            Self::Spectacle => &["spectacle"],
+            Self::Scrot => &["scrot"],
+            Self::Screencapture => &["screencapture"],
+            Self::GnomeScreenshot => &["gnome-screenshot"],
+            Self::Xfce4Screenshooter => &["xfce4-screenshooter"],
+            Self::Custom { .. } => &[],
+        }
+    }
+
+    pub fn installed(&self) -> bool {
+        self.programs().iter().all(|program| on_path(program))
+    }
+
+    /// Supported tools that are installed, best fit first
+    pub fn detect() -> Vec<ScreenshotType> {
+        Self::BUILTIN
+            .into_iter()
+            .filter(|tool| tool.supported() && tool.installed())
+            .collect()
+    }
+
+    fn area_command(&self, file: &str) -> Result<Vec<String>> {
+        Ok(match self {
+            Self::Flameshot => args(&["flameshot", "gui", "-c", "-p", file]),
+            Self::Spectacle => args(&["spectacle", "-rbno", file]),
+            Self::Scrot => args(&["scrot", "--select", file]),
+            Self::Screencapture => args(&["screencapture", "-S", file]),
+            Self::Grim => args(&["grim", "-g", "{geometry}", file]),
+            Self::Maim => args(&["maim", "-s", file]),
+            Self::GnomeScreenshot => args(&["gnome-screenshot", "-a", "-f", file]),
+            Self::Xfce4Screenshooter => args(&["xfce4-screenshooter", "-r", "-s", file]),
+            // import lets the user drag a rectangle when it gets no window
+            Self::Import => args(&["import", file]),
+            Self::Custom { area, shell, .. } => return custom(area, file, *shell),
+        })
+    }
+
+    fn screen_command(&self, file: &str) -> Result<Vec<String>> {
+        Ok(match self {
+            Self::Flameshot => args(&["flameshot", "gui", "-p", file]),
+            Self::Spectacle => args(&["spectacle", "-fbno", file]),
+            Self::Scrot => args(&["scrot", file]),
+            Self::Screencapture => args(&["screencapture", "-S", file]),
+            Self::Grim => args(&["grim", file]),
+            Self::Maim => args(&["maim", file]),
+            Self::GnomeScreenshot => args(&["gnome-screenshot", "-f", file]),
+            Self::Xfce4Screenshooter => args(&["xfce4-screenshooter", "-f", "-s", file]),
+            Self::Import => args(&["import", "-window", "root", file]),
+            Self::Custom { screen, shell, .. } => return custom(screen, file, *shell),
+        })
+    }
+
+    fn window_command(&self, file: &str) -> Result<Vec<String>> {
+        Ok(match self {
+            Self::Flameshot => args(&["flameshot", "gui", "-p", file, "-w"]),
+            Self::Spectacle => args(&["spectacle", "-abno", file]),
+            Self::Scrot => args(&["scrot", "--border", "--focused", file]),
+            Self::Screencapture => args(&["screencapture", "-w", file]),
+            Self::Grim => args(&["grim", "-g", "{window}", file]),
+            Self::Maim => args(&["maim", "-i", "{window}", file]),
+            Self::GnomeScreenshot => args(&["gnome-screenshot", "-w", "-f", file]),
+            Self::Xfce4Screenshooter => args(&["xfce4-screenshooter", "-w", "-s", file]),
+            Self::Import => args(&["import", "-window", "{window}", file]),
+            Self::Custom { window, shell, .. } => return custom(window, file, *shell),
+        })
+    }
+
+    /// The command that saves the screenshot to `path`, area selections and window lookups that the tool can't do
+    /// itself are done first
+    pub async fn command(&self, send: SendScreenshot, path: PathBuf) -> Result<ScreenshotCommand> {
+        let file = path.to_string_lossy().to_string();
+        let mut args = match send {
+            SendScreenshot::Area => self.area_command(&file)?,
+            SendScreenshot::Window => self.window_command(&file)?,
+            SendScreenshot::Screen => self.screen_command(&file)?,
+        };
+        if args.is_empty() {
+            return Err(anyhow!("No screenshot command set for {}", self.name()));
+        }
+        for arg in args.iter_mut() {
+            match arg.as_str() {
+                "{geometry}" => *arg = select_area("slurp", &[]).await?,
+                "{window}" if *self == Self::Grim => *arg = focused_window_geometry().await?,
+                "{window}" => *arg = active_x11_window().await?,
+                _ => {}
+            }
+        }
+        let (env, stdout) = match self {
+            Self::Custom { env, stdout, .. } => (env.clone(), *stdout),
+            _ => (BTreeMap::new(), false),
+        };
+        Ok(ScreenshotCommand {
+            path,
+            args,
+            env,
+            stdout,
+        })
+    }
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Splits the command like a shell would, or hands all of it to `sh -c` with the file as `$1`
+fn custom(command: &str, file: &str, shell: bool) -> Result<Vec<String>> {
+    if command.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    if shell {
+        return Ok(args(&["sh", "-c", &shell_file_parameter(command), "sh", file]));
+    }
+    split_command(command, file)
+}
+
+/// Replaces `{file}` with a reference to `$1` that stays a single word whether the user quoted it or not
+fn shell_file_parameter(command: &str) -> String {
+    let mut out = String::new();
+    let (mut single, mut double) = (false, false);
+    let mut rest = command;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("{file}") {
+            out.push_str(match (single, double) {
+                // leave the single quotes for a moment, nothing expands inside them
+                (true, _) => "'\"$1\"'",
+                (_, true) => "$1",
+                _ => "\"$1\"",
+            });
+            rest = after;
+            continue;
+        }
+        match c {
+            '\\' if !single => {
+                out.push(c);
+                rest = &rest[1..];
+                if let Some(escaped) = rest.chars().next() {
+                    out.push(escaped);
+                    rest = &rest[escaped.len_utf8()..];
                 }
-                Self::Custom { window, .. } => window.replace("{file}", &file),
-            },
-        )
+                continue;
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            _ => {}
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Asks the user to select an area with `program` and returns what it printed
+pub async fn select_area(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program).args(args).output().await.map_err(|e| {
+        if e.kind() == ErrorKind::NotFound {
+            anyhow!("{program} is not installed, it is needed to select an area")
+        } else {
+            anyhow!("Failed running {program} {e}")
+        }
+    })?;
+    if !output.status.success() {
+        return Err(anyhow!("Area selection cancelled"));
     }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Id of the focused X11 window
+async fn active_x11_window() -> Result<String> {
+    let output = Command::new("xdotool")
+        .arg("getactivewindow")
+        .output()
+        .await
+        .map_err(|e| anyhow!("Failed running xdotool, it is needed to capture a window {e}"))?;
+    if !output.status.success() {
+        return Err(anyhow!("xdotool could not find the focused window"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
-    pub fn cmd_from_type(&self, send: SendScreenshot) -> (String, String) {
-        match send {
-            SendScreenshot::Area => self.area_command(),
-            SendScreenshot::Window => self.window_command(),
-            SendScreenshot::Screen => self.screen_command(),
+/// Geometry of the focused window in the `x,y wxh` format grim takes, asks Hyprland first and then Sway
+async fn focused_window_geometry() -> Result<String> {
+    if let Ok(output) = Command::new("hyprctl").args(["-j", "activewindow"]).output().await {
+        if output.status.success() {
+            let window: Value = serde_json::from_slice(&output.stdout)?;
+            let (at, size) = (&window["at"], &window["size"]);
+            if let (Some(x), Some(y), Some(w), Some(h)) =
+                (at[0].as_i64(), at[1].as_i64(), size[0].as_i64(), size[1].as_i64())
+            {
+                return Ok(format!("{x},{y} {w}x{h}"));
+            }
         }
     }
+
+    let output = Command::new("swaymsg")
+        .args(["-t", "get_tree"])
+        .output()
+        .await
+        .map_err(|_| anyhow!("Capturing a window with grim needs Hyprland or Sway"))?;
+    let tree: Value = serde_json::from_slice(&output.stdout)?;
+    let rect = &find_focused(&tree).ok_or_else(|| anyhow!("Sway has no focused window"))?["rect"];
+    Ok(format!(
+        "{},{} {}x{}",
+        rect["x"], rect["y"], rect["width"], rect["height"]
+    ))
+}
+
+/// Title of the focused window on Hyprland, Sway or X11
+pub async fn focused_window_title() -> Option<String> {
+    if let Ok(output) = Command::new("hyprctl").args(["-j", "activewindow"]).output().await {
+        if output.status.success() {
+            let window: Value = serde_json::from_slice(&output.stdout).ok()?;
+            if let Some(title) = window["title"].as_str() {
+                return Some(title.to_string());
+            }
+        }
+    }
+    if let Ok(output) = Command::new("swaymsg").args(["-t", "get_tree"]).output().await {
+        if output.status.success() {
+            let tree: Value = serde_json::from_slice(&output.stdout).ok()?;
+            return find_focused(&tree)?["name"].as_str().map(String::from);
+        }
+    }
+    let output = Command::new("xdotool")
+        .args(["getactivewindow", "getwindowname"])
+        .output()
+        .await
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The focused node of a Sway tree
+fn find_focused(node: &Value) -> Option<&Value> {
+    if node["focused"].as_bool() == Some(true) && node["type"] != "workspace" {
+        return Some(node);
+    }
+    node["nodes"]
+        .as_array()
+        .into_iter()
+        .chain(node["floating_nodes"].as_array())
+        .flatten()
+        .find_map(find_focused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "/tmp/my shot's.png";
+
+    /// Runs the shell command and returns what it printed
+    #[cfg(unix)]
+    fn run(command: &str) -> String {
+        let args = custom(command, FILE, true).unwrap();
+        let output = std::process::Command::new(&args[0]).args(&args[1..]).output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_file_stays_one_argument() {
+        let expected = format!("<{FILE}>\n");
+        assert_eq!(run("printf '<%s>\\n' {file}"), expected);
+        assert_eq!(run("printf '<%s>\\n' \"{file}\""), expected);
+        assert_eq!(run("printf '<%s>\\n' '{file}'"), expected);
+        assert_eq!(run("printf '<%s>\\n' \"x {file}\""), format!("<x {FILE}>\n"));
+        assert_eq!(run("printf '<%s>\\n' \\{file}"), "<{file}>\n");
+    }
+
+    #[test]
+    fn split_without_shell() {
+        assert_eq!(
+            custom("grim -t png '{file}'", FILE, false).unwrap(),
+            ["grim", "-t", "png", FILE]
+        );
+        assert!(custom("  ", FILE, false).unwrap().is_empty());
+        assert!(custom("grim 'unclosed", FILE, false).is_err());
+    }
 }
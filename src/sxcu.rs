@@ -0,0 +1,310 @@
+//! ShareX custom uploader (.sxcu) support
+//!
+//! Parses uploader files and evaluates the `{json:...}`, `{regex:...}` and `{header:...}` syntax used in the
+//! `URL`, `DeletionURL` and `ErrorMessage` fields, see <https://getsharex.com/docs/custom-uploader>
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ascella_config::{UploaderProfile, DEFAULT_API_URL};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum BodyType {
+    None,
+    #[default]
+    MultipartFormData,
+    #[serde(rename = "FormURLEncoded")]
+    FormUrlEncoded,
+    #[serde(rename = "JSON")]
+    Json,
+    Binary,
+}
+
+impl BodyType {
+    pub const ALL: [BodyType; 5] = [
+        Self::MultipartFormData,
+        Self::Binary,
+        Self::FormUrlEncoded,
+        Self::Json,
+        Self::None,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::MultipartFormData => "Multipart form data",
+            Self::FormUrlEncoded => "Form URL encoded",
+            Self::Json => "JSON",
+            Self::Binary => "Binary",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Sxcu {
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "RequestURL")]
+    pub request_url: String,
+    #[serde(default)]
+    pub request_method: Option<String>,
+    #[serde(default)]
+    pub parameters: HashMap<String, String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<BodyType>,
+    #[serde(default)]
+    pub arguments: HashMap<String, String>,
+    #[serde(default)]
+    pub file_form_name: Option<String>,
+    #[serde(default)]
+    pub data: String,
+    #[serde(default, rename = "URL")]
+    pub url: String,
+    #[serde(default, rename = "DeletionURL")]
+    pub deletion_url: String,
+    #[serde(default)]
+    pub error_message: String,
+}
+
+impl Sxcu {
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    /// Converts the uploader into a profile, the ascella token header becomes the profile api key
+    pub fn into_profile(mut self) -> UploaderProfile {
+        let api_key = self.headers.remove("ascella-token").unwrap_or_default();
+        let api_url = match self.request_url.strip_suffix("/upload") {
+            Some(api_url) if !api_key.is_empty() => api_url.to_string(),
+            _ => DEFAULT_API_URL.to_string(),
+        };
+        let name = if !self.name.is_empty() {
+            self.name
+        } else {
+            reqwest::Url::parse(&self.request_url)
+                .ok()
+                .and_then(|url| url.host_str().map(ToString::to_string))
+                .unwrap_or_else(|| "imported".to_string())
+        };
+        let mut profile = UploaderProfile {
+            name,
+            api_url,
+            api_key,
+            request_url: self.request_url,
+            headers: self.headers,
+            parameters: self.parameters,
+            arguments: self.arguments,
+            data: self.data,
+            url: self.url,
+            deletion_url: self.deletion_url,
+            error_message: self.error_message,
+            ..Default::default()
+        };
+        if let Some(method) = self.request_method {
+            profile.request_method = method.to_uppercase();
+        }
+        if let Some(body) = self.body {
+            profile.body = body;
+        }
+        if let Some(file_form_name) = self.file_form_name {
+            profile.file_form_name = file_form_name;
+        }
+        profile
+    }
+}
+
+/// Request side syntax ShareX has that we don't evaluate, uploaders using it are rejected instead of sending the
+/// syntax as is
+const UNSUPPORTED_ARGUMENTS: [&str; 7] = ["input", "random", "select", "prompt", "inputbox", "outputbox", "base64"];
+
+fn check_argument(value: &str) -> Result<()> {
+    for name in UNSUPPORTED_ARGUMENTS {
+        if value.contains(&format!("{{{name}}}")) || value.contains(&format!("{{{name}:")) {
+            return Err(anyhow!("Unsupported syntax {{{name}}} in {value}"));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `{filename}` in argument values, the only request side syntax we support
+pub fn parse_argument(value: &str, filename: &str) -> Result<String> {
+    check_argument(value)?;
+    Ok(value.replace("{filename}", filename))
+}
+
+/// Same as `parse_argument` for the JSON `Data` body, the filename is escaped so quotes in it can't break the body
+pub fn parse_json_data(data: &str, filename: &str) -> Result<String> {
+    check_argument(data)?;
+    let escaped = serde_json::to_string(filename)?;
+    let data = data.replace("{filename}", &escaped[1..escaped.len() - 1]);
+    serde_json::from_str::<Value>(&data).map_err(|e| anyhow!("Data is not valid JSON: {e}"))?;
+    Ok(data)
+}
+
+/// Evaluates a response template such as `https://example.com/{json:files[0].id}`
+pub fn parse_template(template: &str, response: &str, headers: &HeaderMap) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            '{' => {
+                let mut depth = 1;
+                let mut inner = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => {
+                            inner.push('\\');
+                            inner.extend(chars.next());
+                        }
+                        Some('{') => {
+                            depth += 1;
+                            inner.push('{');
+                        }
+                        Some('}') => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                            inner.push('}');
+                        }
+                        Some(c) => inner.push(c),
+                        None => return Err(anyhow!("Unclosed {{ in template {template}")),
+                    }
+                }
+                out.push_str(&evaluate(&inner, response, headers)?);
+            }
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+fn evaluate(syntax: &str, response: &str, headers: &HeaderMap) -> Result<String> {
+    let (name, args) = syntax.split_once(':').unwrap_or((syntax, ""));
+    match name {
+        "response" => Ok(response.to_string()),
+        "json" => {
+            let json: Value = serde_json::from_str(response)?;
+            json_path(&json, args)
+                .map(|value| match value {
+                    Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                })
+                .ok_or_else(|| anyhow!("{args} not found in response"))
+        }
+        "regex" => {
+            let (pattern, group) = match args.rsplit_once('|') {
+                Some((pattern, group)) if !group.is_empty() && group.chars().all(char::is_alphanumeric) => {
+                    (pattern, group)
+                }
+                _ => (args, "0"),
+            };
+            let regex = Regex::new(pattern)?;
+            let captures = regex
+                .captures(response)
+                .ok_or_else(|| anyhow!("{pattern} did not match the response"))?;
+            let found = match group.parse::<usize>() {
+                Ok(index) => captures.get(index),
+                Err(_) => captures.name(group),
+            };
+            Ok(found.map(|m| m.as_str().to_string()).unwrap_or_default())
+        }
+        "header" => Ok(headers
+            .get(args)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()),
+        _ => Err(anyhow!("Unsupported syntax {{{syntax}}}")),
+    }
+}
+
+/// Minimal JSONPath, supports `data.url`, `$.files[0].url` and `[0]`
+fn json_path<'a>(json: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim_start_matches('$').trim_start_matches('.');
+    let mut value = json;
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (key, indexes) = segment.split_once('[').unwrap_or((segment, ""));
+        if !key.is_empty() {
+            value = value.get(key)?;
+        }
+        for index in indexes.split('[').filter(|s| !s.is_empty()) {
+            let index = index.trim_end_matches(']');
+            value = match index.parse::<usize>() {
+                Ok(index) => value.get(index)?,
+                Err(_) => value.get(index.trim_matches(|c| c == '\'' || c == '"'))?,
+            };
+        }
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    const RESPONSE: &str = r#"{"data":{"url":"https://i.example.com/a.png"},"files":[{"id":"abc","size":3}]}"#;
+
+    fn template(template: &str) -> Result<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert("location", HeaderValue::from_static("https://example.com/abc"));
+        parse_template(template, RESPONSE, &headers)
+    }
+
+    #[test]
+    fn json_paths() {
+        assert_eq!(template("{json:data.url}").unwrap(), "https://i.example.com/a.png");
+        assert_eq!(
+            template("https://x.com/{json:$.files[0].id}").unwrap(),
+            "https://x.com/abc"
+        );
+        assert_eq!(template("{json:files[0].size}").unwrap(), "3");
+        assert!(template("{json:files[1].id}").is_err());
+    }
+
+    #[test]
+    fn regex_groups() {
+        assert_eq!(template(r#"{regex:"id":"(\w+)"|1}"#).unwrap(), "abc");
+        assert_eq!(template(r#"{regex:"id":"(?P<id>\w+)"|id}"#).unwrap(), "abc");
+        assert_eq!(template("{regex:a\\.png}").unwrap(), "a.png");
+        assert!(template("{regex:nothing here}").is_err());
+    }
+
+    #[test]
+    fn headers_and_escapes() {
+        assert_eq!(
+            template("{header:location}/delete").unwrap(),
+            "https://example.com/abc/delete"
+        );
+        assert_eq!(template("{header:missing}").unwrap(), "");
+        assert_eq!(template("\\{json:data.url\\}").unwrap(), "{json:data.url}");
+        assert!(template("{json:data.url").is_err());
+        assert!(template("{unknown}").is_err());
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(parse_argument("ascella-{filename}", "a.png").unwrap(), "ascella-a.png");
+        assert!(parse_argument("{random:a|b}", "a.png").is_err());
+        assert!(parse_argument("{input}", "a.png").is_err());
+    }
+
+    #[test]
+    fn json_data_is_escaped() {
+        let data = parse_json_data(r#"{"name":"{filename}"}"#, "a \"b\".png").unwrap();
+        let json: Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(json["name"], "a \"b\".png");
+        assert!(parse_json_data("{filename}", "a.png").is_err());
+    }
+}
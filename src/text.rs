@@ -0,0 +1,65 @@
+//! Text rasterizing shared by the annotation editor and the watermark stage
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use eframe::egui::{Color32, Pos2};
+use image::RgbaImage;
+
+const FONT: &[u8] = include_bytes!("./OpenSans-Regular.ttf");
+
+/// Width and height of a line of text drawn with `draw_text`
+pub fn measure_text(text: &str, size: f32) -> (f32, f32) {
+    let Ok(font_ref) = FontRef::try_from_slice(FONT) else {
+        return (0.0, 0.0);
+    };
+    let font = font_ref.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut last = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(last) = last {
+            width += font.kern(last, id);
+        }
+        width += font.h_advance(id);
+        last = Some(id);
+    }
+    (width, font.height())
+}
+
+/// Draws a line of text with its top left corner at `pos`, the alpha of the color is used as opacity
+pub fn draw_text(image: &mut RgbaImage, pos: Pos2, text: &str, color: Color32, size: f32) {
+    let Ok(font_ref) = FontRef::try_from_slice(FONT) else {
+        return;
+    };
+    let font = font_ref.as_scaled(PxScale::from(size));
+    let color = color.to_srgba_unmultiplied();
+    let opacity = color[3] as f32 / 255.0;
+    let mut caret = point(pos.x, pos.y + font.ascent());
+    let mut last = None;
+    for c in text.chars() {
+        let mut glyph = font.scaled_glyph(c);
+        if let Some(last) = last {
+            caret.x += font.kern(last, glyph.id);
+        }
+        last = Some(glyph.id);
+        glyph.position = caret;
+        caret.x += font.h_advance(glyph.id);
+
+        let Some(outline) = font_ref.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|x, y, coverage| {
+            let coverage = coverage * opacity;
+            let x = bounds.min.x as i64 + x as i64;
+            let y = bounds.min.y as i64 + y as i64;
+            if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+                return;
+            }
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            for (channel, target) in pixel.0.iter_mut().zip(color).take(3) {
+                *channel = (*channel as f32 * (1.0 - coverage) + target as f32 * coverage) as u8;
+            }
+            pixel[3] = pixel[3].max((coverage * 255.0) as u8);
+        });
+    }
+}
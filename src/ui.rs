@@ -1,4 +1,9 @@
-use std::{fs, path::PathBuf, time::Duration};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use eframe::{
     egui::{self, Button, Frame, Margin, RichText, Rounding, Window},
@@ -7,16 +12,25 @@ use eframe::{
 use egui_file::FileDialog;
 use egui_notify::Toasts;
 use egui_tracing::EventCollector;
-use reqwest::{header::HeaderValue, Method};
 use serde::Deserialize;
-use serde_json::Value;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 use crate::{
     ascella_config::AscellaConfig,
     easy_mark,
-    screens::{self, history::AscellaFile},
+    editor::{Editor, EditorAction},
+    pipeline::StageStatus,
+    redaction::RedactionPreview,
+    request_handler::{Countdown, UploadProgress},
+    retention::CleanupReport,
+    screens::{
+        self,
+        history::{AscellaFile, HistorySort},
+    },
+    sxcu::Sxcu,
     theme::{set_theme, Theme},
+    upload_history::{self, HistoryEntry},
+    upload_queue::{JobState, UploadJob},
     utils::theme_number_to_theme,
     Request, RequestResponse, RequestType,
 };
@@ -35,6 +49,8 @@ pub struct MyApp {
     pub config: AscellaConfig,
     pub opened_file: Option<PathBuf>,
     pub open_file_dialog: Option<FileDialog>,
+    pub upload_file_dialog: Option<FileDialog>,
+    pub redaction_file_dialog: Option<FileDialog>,
     pub theme: Theme,
 
     pub sender: UnboundedSender<Request>,
@@ -49,6 +65,30 @@ pub struct MyApp {
 
     pub history: Vec<AscellaFile>,
     pub history_index: u64,
+
+    pub upload_jobs: Vec<UploadJob>,
+    /// progress of the running queue uploads by job id
+    pub upload_progress: BTreeMap<u64, UploadProgress>,
+    pub pipeline_stage: Option<StageStatus>,
+    /// when the running recording started
+    pub recording: Option<Instant>,
+    /// seconds to wait before screenshots from the home screen are taken
+    pub capture_delay: u32,
+    pub countdown: Option<Countdown>,
+
+    pub local_history: Vec<HistoryEntry>,
+    pub history_search: String,
+    pub history_sort: HistorySort,
+    pub history_page: usize,
+    /// entry waiting for the user to confirm its deletion
+    pub pending_delete: Option<HistoryEntry>,
+
+    pub editor: Option<Editor>,
+    /// captures waiting for the editor to be closed
+    pub pending_annotations: VecDeque<(PathBuf, Box<AscellaConfig>)>,
+    pub redaction_preview: Option<RedactionPreview>,
+    /// last cleanup or dry run, shown in the settings
+    pub cleanup_report: Option<CleanupReport>,
 }
 
 impl MyApp {
@@ -65,6 +105,8 @@ impl MyApp {
             sender,
             receiver,
             open_file_dialog: None,
+            upload_file_dialog: None,
+            redaction_file_dialog: None,
             opened_file: None,
             user: None,
             collector,
@@ -75,10 +117,66 @@ impl MyApp {
                 .with_anchor(egui_notify::Anchor::TopLeft),
             history: Vec::new(),
             history_index: 0,
+            upload_jobs: Vec::new(),
+            upload_progress: BTreeMap::new(),
+            pipeline_stage: None,
+            recording: None,
+            capture_delay: 0,
+            countdown: None,
+            local_history: load_history(),
+            history_search: String::new(),
+            history_sort: HistorySort::default(),
+            history_page: 0,
+            pending_delete: None,
+            editor: None,
+            pending_annotations: VecDeque::new(),
+            redaction_preview: None,
+            cleanup_report: None,
+        }
+    }
+}
+
+impl MyApp {
+    /// Imports a ShareX custom uploader as a profile and switches to it
+    pub fn import_sxcu(&mut self, data: &[u8]) {
+        let profile = match Sxcu::from_slice(data) {
+            Ok(sxcu) => sxcu.into_profile(),
+            Err(e) => {
+                self.toasts.error(format!("Invalid custom uploader\n{e}"));
+                return;
+            }
+        };
+        self.toasts.info(format!("Imported profile {}", profile.name));
+        self.config.import_profile(profile);
+        self.user = None;
+        self.retrieving_user = false;
+
+        self.sender.send(Request::SaveConfig(self.config.clone())).ok();
+    }
+
+    /// Queues files for upload with the current profile
+    pub fn upload_files(&mut self, paths: Vec<PathBuf>) {
+        let paths: Vec<PathBuf> = paths.into_iter().filter(|path| path.is_file()).collect();
+        if paths.is_empty() {
+            return;
         }
+        self.toasts.info(format!("Uploading {} file(s)", paths.len()));
+        self.sender
+            .send(Request::Upload {
+                paths,
+                config: self.config.clone(),
+            })
+            .ok();
     }
 }
 
+fn load_history() -> Vec<HistoryEntry> {
+    upload_history::load().unwrap_or_else(|e| {
+        tracing::error!("Failed loading upload history {e:?}");
+        Vec::new()
+    })
+}
+
 #[derive(Deserialize, Debug)]
 #[allow(dead_code)]
 pub struct AscellaUser {
@@ -192,104 +290,236 @@ impl eframe::App for MyApp {
                 );
             });
 
-        macro_rules! i_hate_borrow_checker {
-            ($data:expr) => {
-                let raw: Value = serde_json::from_slice($data).unwrap();
-
-                self.config.headers = serde_json::from_value(raw["Headers"].clone()).unwrap();
-
-                self.config.request_url = raw["RequestURL"].as_str().unwrap().to_owned();
-
-                if let Some(token) = self.config.headers.remove("ascella-token") {
-                    self.config.api_key = token.clone();
-                    let mut req = reqwest::Request::new(
-                        Method::GET,
-                        format!("{}/me", self.config.api_url).parse().unwrap(),
-                    );
-                    req.headers_mut()
-                        .append("ascella-token", HeaderValue::from_str(&token).unwrap());
-
-                    self.sender
-                        .send(Request::DoRequest {
-                            r_type: RequestType::RetrieveUser,
-                            request: req,
-                        })
-                        .ok();
+        let mut imported = None;
+        if let Some(dialog) = &mut self.open_file_dialog {
+            if dialog.show(ctx).selected() {
+                if let Some(file) = dialog.path() {
+                    imported = Some(file.clone());
                 }
-                self.toasts.info("Updated Config");
-
-                self.sender.send(Request::SaveConfig(self.config.clone())).ok();
-            };
+            }
         }
-
-        if let Some(dialog) = &mut self.open_file_dialog {
+        let mut uploaded = None;
+        if let Some(dialog) = &mut self.upload_file_dialog {
             if dialog.show(ctx).selected() {
                 if let Some(file) = dialog.path() {
-                    self.opened_file = Some(file.clone());
-                    let raw = &fs::read(file).unwrap();
-                    i_hate_borrow_checker!(raw);
+                    uploaded = Some(file.clone());
                 }
             }
         }
+        if let Some(file) = uploaded {
+            self.opened_file = Some(file.clone());
+            self.upload_files(vec![file]);
+        }
 
-        if !self.retrieving_user && self.user.is_none() {
-            let mut req = reqwest::Request::new(Method::GET, format!("{}/me", self.config.api_url).parse().unwrap());
-            req.headers_mut()
-                .append("ascella-token", HeaderValue::from_str(&self.config.api_key).unwrap());
+        let dropped: Vec<PathBuf> = ctx.input(|i| i.raw.dropped_files.iter().filter_map(|f| f.path.clone()).collect());
+        if !dropped.is_empty() {
+            self.upload_files(dropped);
+        }
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("file_drop")));
+            let screen = ctx.screen_rect();
+            painter.rect_filled(screen, 0.0, egui::Color32::from_black_alpha(192));
+            painter.text(
+                screen.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop files to upload",
+                egui::FontId::proportional(20.0),
+                egui::Color32::WHITE,
+            );
+        }
 
+        let mut previewed = None;
+        if let Some(dialog) = &mut self.redaction_file_dialog {
+            if dialog.show(ctx).selected() {
+                if let Some(file) = dialog.path() {
+                    previewed = Some(file.clone());
+                }
+            }
+        }
+        if let Some(path) = previewed {
+            self.opened_file = Some(path.clone());
             self.sender
-                .send(Request::DoRequest {
-                    r_type: RequestType::RetrieveUser,
-                    request: req,
+                .send(Request::PreviewRedaction {
+                    path,
+                    config: self.config.redaction.clone(),
                 })
                 .ok();
-            self.retrieving_user = true;
+        }
+        if let Some(preview) = &mut self.redaction_preview {
+            if !preview.show(ctx) {
+                self.redaction_preview = None;
+            }
         }
 
-        match self.receiver.try_recv() {
-            Ok(RequestResponse::Request {
-                content,
-                r_type,
-                status,
-            }) => match r_type {
-                RequestType::RetrieveUser => {
-                    if status.is_success() {
-                        let data: AscellaUserEndpointResult<AscellaUser> = serde_json::from_slice(&content).unwrap();
-                        self.user = Some(data.data);
-                        self.toasts.success("Received user info");
-                    } else {
-                        self.toasts
-                            .error(format!("Failed receiving user from token {}", status,));
+        match self.editor.as_mut().map(|editor| editor.show(ctx)) {
+            Some(EditorAction::Upload) => {
+                if let Some(editor) = self.editor.take() {
+                    match editor.save() {
+                        Ok(()) => {
+                            self.sender
+                                .send(Request::UploadCapture {
+                                    path: editor.path,
+                                    config: editor.config,
+                                })
+                                .ok();
+                        }
+                        Err(e) => {
+                            self.toasts.error(format!("Failed saving annotations\n{e}"));
+                            self.editor = Some(editor);
+                        }
                     }
                 }
-                RequestType::RequestPage => {
-                    if status.is_success() {
-                        let data: AscellaUserEndpointResult<Vec<AscellaFile>> =
-                            serde_json::from_slice(&content).unwrap();
-                        if data.data.is_empty() {
+            }
+            Some(EditorAction::Cancel) => self.editor = None,
+            Some(EditorAction::None) | None => {}
+        }
+
+        if let Some(file) = imported {
+            self.opened_file = Some(file.clone());
+            match fs::read(&file) {
+                Ok(raw) => self.import_sxcu(&raw),
+                Err(e) => {
+                    self.toasts.error(format!("Failed reading {}\n{e}", file.display()));
+                }
+            }
+        }
+
+        if !self.retrieving_user && self.user.is_none() && !self.config.profile().api_key.is_empty() {
+            // not retried until the profile changes, so a bad url only shows one toast
+            match self.config.profile().api_request("/me") {
+                Ok(request) => {
+                    self.sender
+                        .send(Request::DoRequest {
+                            r_type: RequestType::RetrieveUser,
+                            request,
+                        })
+                        .ok();
+                }
+                Err(e) => {
+                    self.toasts.error(format!("Failed retrieving user info\n{e:#}"));
+                }
+            }
+            self.retrieving_user = true;
+        }
+
+        while let Ok(response) = self.receiver.try_recv() {
+            match response {
+                RequestResponse::Request {
+                    content,
+                    r_type,
+                    status,
+                } => match r_type {
+                    RequestType::RetrieveUser => {
+                        if status.is_success() {
+                            match serde_json::from_slice::<AscellaUserEndpointResult<AscellaUser>>(&content) {
+                                Ok(data) => {
+                                    self.user = Some(data.data);
+                                    self.toasts.success("Received user info");
+                                }
+                                Err(e) => {
+                                    self.toasts.error(format!("Invalid user info from the api\n{e}"));
+                                }
+                            }
+                        } else {
                             self.toasts
-                                .error("Reached End....")
-                                .set_duration(Some(Duration::from_secs(1)));
-                            self.history_index -= 1;
+                                .error(format!("Failed receiving user from token {}", status,));
+                        }
+                    }
+                    RequestType::RequestPage => {
+                        if status.is_success() {
+                            match serde_json::from_slice::<AscellaUserEndpointResult<Vec<AscellaFile>>>(&content) {
+                                Ok(data) if data.data.is_empty() => {
+                                    self.toasts
+                                        .error("Reached End....")
+                                        .set_duration(Some(Duration::from_secs(1)));
+                                    self.history_index -= 1;
+                                }
+                                Ok(data) => {
+                                    self.toasts
+                                        .success("Files loaded....")
+                                        .set_duration(Some(Duration::from_secs(1)));
+                                    self.history.extend(data.data);
+                                }
+                                Err(e) => {
+                                    self.toasts.error(format!("Invalid history from the api\n{e}"));
+                                    self.history_index -= 1;
+                                }
+                            }
                         } else {
                             self.toasts
-                                .success("Files loaded....")
-                                .set_duration(Some(Duration::from_secs(1)));
+                                .error(format!("Failed receiving history from token {}", status,));
                         }
-                        self.history.extend(data.data);
-                    } else {
+                    }
+                },
+                RequestResponse::Toast(toast) => {
+                    self.toasts.add(toast);
+                }
+                RequestResponse::UpdateConfigFromStringSxcu(data) => {
+                    self.import_sxcu(&data);
+                }
+                RequestResponse::HistoryUpdated => {
+                    self.local_history = load_history();
+                }
+                RequestResponse::QueueUpdated(jobs) => {
+                    // cancelled jobs never report that they finished
+                    self.upload_progress
+                        .retain(|id, _| jobs.iter().any(|job| job.id == *id && job.state == JobState::Pending));
+                    self.upload_jobs = jobs;
+                }
+                RequestResponse::UploadProgress { id, progress } => {
+                    self.upload_progress.insert(id, progress);
+                }
+                RequestResponse::UploadFinished(id) => {
+                    self.upload_progress.remove(&id);
+                }
+                RequestResponse::PipelineStage(stage) => {
+                    self.pipeline_stage = stage;
+                }
+                RequestResponse::Recording(started) => {
+                    self.recording = started;
+                }
+                RequestResponse::Countdown(countdown) => {
+                    self.countdown = countdown;
+                }
+                RequestResponse::CleanupReport(report) => {
+                    self.cleanup_report = Some(report);
+                }
+                RequestResponse::RedactionPreview { path, regions } => match RedactionPreview::new(&path, regions) {
+                    Ok(preview) => self.redaction_preview = Some(preview),
+                    Err(e) => {
+                        self.toasts.error(format!("Failed opening {}\n{e}", path.display()));
+                    }
+                },
+                RequestResponse::Annotate { path, config } => {
+                    if self.editor.is_some() {
                         self.toasts
-                            .error(format!("Failed receiving history from token {}", status,));
+                            .info("Capture queued, it opens once the current one is closed");
                     }
+                    self.pending_annotations.push_back((path, config));
                 }
-            },
-            Ok(RequestResponse::Toast(toast)) => {
-                self.toasts.add(toast);
             }
-            Ok(RequestResponse::UpdateConfigFromStringSxcu(data)) => {
-                i_hate_borrow_checker!(&data);
+        }
+
+        while self.editor.is_none() {
+            let Some((path, config)) = self.pending_annotations.pop_front() else {
+                break;
+            };
+            match Editor::open(path, *config) {
+                Ok(editor) => self.editor = Some(editor),
+                Err(e) => {
+                    self.toasts.error(e.to_string());
+                }
             }
-            _ => {}
+        }
+
+        if !self.upload_progress.is_empty()
+            || self.pipeline_stage.is_some()
+            || self.recording.is_some()
+            || self.countdown.is_some()
+            || self.upload_jobs.iter().any(|job| job.state == JobState::Pending)
+        {
+            // the worker can't wake up the ui so keep polling while uploads are running
+            ctx.request_repaint_after(Duration::from_millis(100));
         }
     }
 }
@@ -0,0 +1,102 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::ascella_dir;
+
+/// A single upload stored in the local history, one json object per line in `history.jsonl`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub url: String,
+    #[serde(default)]
+    pub delete_url: String,
+    #[serde(default)]
+    pub metadata: String,
+    pub path: PathBuf,
+    /// unix timestamp in seconds
+    pub timestamp: i64,
+    pub size: u64,
+    #[serde(default)]
+    pub mime: String,
+    #[serde(default)]
+    pub uploader: String,
+}
+
+static LAST_ID: AtomicI64 = AtomicI64::new(0);
+
+/// A new history id, the current time in milliseconds bumped past the last id so two uploads in the same millisecond
+/// don't share one
+pub fn next_id() -> i64 {
+    let now = chrono::Utc::now().timestamp_millis();
+    let last = LAST_ID
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(now.max(last + 1)))
+        .unwrap_or_else(|last| last);
+    now.max(last + 1)
+}
+
+fn history_file() -> PathBuf {
+    ascella_dir().join("history.jsonl")
+}
+
+pub fn load() -> Result<Vec<HistoryEntry>> {
+    let data = match fs::read_to_string(history_file()) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                tracing::warn!("Skipping broken history entry {e:?}");
+                None
+            }
+        })
+        .collect())
+}
+
+pub fn append(entry: &HistoryEntry) -> Result<()> {
+    fs::create_dir_all(ascella_dir())?;
+    let mut file = OpenOptions::new().create(true).append(true).open(history_file())?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Finds an entry by its id or url
+pub fn find(id_or_url: &str) -> Result<Option<HistoryEntry>> {
+    let id = id_or_url.parse::<i64>().ok();
+    Ok(load()?
+        .into_iter()
+        .find(|entry| Some(entry.id) == id || entry.url == id_or_url))
+}
+
+pub fn remove(id: i64) -> Result<()> {
+    let mut data = String::new();
+    for entry in load()?.into_iter().filter(|entry| entry.id != id) {
+        data.push_str(&serde_json::to_string(&entry)?);
+        data.push('\n');
+    }
+    fs::write(history_file(), data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_unique() {
+        let ids: Vec<i64> = (0..1000).map(|_| next_id()).collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+}
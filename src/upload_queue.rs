@@ -0,0 +1,225 @@
+use std::{collections::HashMap, fs, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use egui_notify::Toast;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    ascella_config::AscellaConfig,
+    request_handler::{upload_file, ProgressCallback, UploadError, UploadProgress},
+    utils::ascella_dir,
+    Request, RequestResponse,
+};
+
+const MAX_ATTEMPTS: u32 = 5;
+const BASE_DELAY: Duration = Duration::from_secs(2);
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JobState {
+    Pending,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UploadJob {
+    pub id: u64,
+    pub path: PathBuf,
+    /// config at the time the job was created so restarts upload to the same profile
+    pub config: AscellaConfig,
+    pub attempts: u32,
+    /// unix timestamp in milliseconds
+    pub next_attempt: i64,
+    pub state: JobState,
+}
+
+/// Uploads waiting to be sent, stored in `queue.json` so they survive restarts
+#[derive(Default)]
+pub struct UploadQueue {
+    jobs: Vec<UploadJob>,
+    /// jobs uploading on their own task, cancelling the token stops the upload
+    running: HashMap<u64, CancellationToken>,
+}
+
+/// How long to wait before the next attempt after `attempts` failed ones
+fn backoff(attempts: u32) -> Duration {
+    BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_DELAY)
+}
+
+fn queue_file() -> PathBuf {
+    ascella_dir().join("queue.json")
+}
+
+impl UploadQueue {
+    pub fn load() -> Self {
+        let jobs = match fs::read(queue_file()) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                tracing::error!("Failed parsing upload queue {e:?}");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            jobs,
+            running: HashMap::new(),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::write(queue_file(), serde_json::to_vec(&self.jobs)?)?;
+        Ok(())
+    }
+
+    fn changed(&self, sender: &UnboundedSender<RequestResponse>) {
+        if let Err(e) = self.save() {
+            tracing::error!("Failed saving upload queue {e:?}");
+        }
+        sender.send(RequestResponse::QueueUpdated(self.jobs.clone())).ok();
+    }
+
+    pub fn jobs(&self) -> &[UploadJob] {
+        &self.jobs
+    }
+
+    pub fn push(&mut self, path: PathBuf, config: AscellaConfig, sender: &UnboundedSender<RequestResponse>) {
+        let id = self.jobs.iter().map(|job| job.id + 1).max().unwrap_or(0);
+        self.jobs.push(UploadJob {
+            id,
+            path,
+            config,
+            attempts: 0,
+            next_attempt: 0,
+            state: JobState::Pending,
+        });
+        self.changed(sender);
+    }
+
+    /// Retries a job right away using the latest config while keeping the profile of the job
+    pub fn retry(&mut self, id: u64, mut config: AscellaConfig, sender: &UnboundedSender<RequestResponse>) {
+        if let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) {
+            config.select_profile(&job.config.default_profile).ok();
+            job.config = config;
+            job.attempts = 0;
+            job.next_attempt = 0;
+            job.state = JobState::Pending;
+        }
+        self.changed(sender);
+    }
+
+    pub fn cancel(&mut self, id: u64, sender: &UnboundedSender<RequestResponse>) {
+        if let Some(cancel) = self.running.remove(&id) {
+            cancel.cancel();
+        }
+        self.jobs.retain(|job| job.id != id);
+        self.changed(sender);
+    }
+
+    /// Starts uploading every pending job whose backoff has passed, each job runs on its own task so the worker keeps
+    /// handling requests and reports back with `Request::UploadFinished`
+    pub fn process(
+        &mut self,
+        client: &reqwest::Client,
+        sender: &UnboundedSender<RequestResponse>,
+        requests: &UnboundedSender<Request>,
+    ) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let due = self.jobs.iter().filter(|job| {
+            job.state == JobState::Pending && job.next_attempt <= now && !self.running.contains_key(&job.id)
+        });
+
+        let mut started = Vec::new();
+        for job in due {
+            let cancel = CancellationToken::new();
+            started.push((job.id, cancel.clone()));
+            let (job, client, requests) = (job.clone(), client.clone(), requests.clone());
+            let id = job.id;
+            let progress_sender = sender.clone();
+            let progress: ProgressCallback = Arc::new(move |progress: UploadProgress| {
+                progress_sender
+                    .send(RequestResponse::UploadProgress { id, progress })
+                    .ok();
+            });
+            tokio::spawn(async move {
+                let upload = async {
+                    if !job.path.exists() {
+                        return Err(anyhow::anyhow!("{} no longer exists", job.path.display()));
+                    }
+                    let res = upload_file(job.path.clone(), &job.config, &client, false, Some(progress)).await?;
+                    Ok::<_, anyhow::Error>(res.url)
+                };
+                tokio::select! {
+                    result = upload => {
+                        requests.send(Request::UploadFinished { id: job.id, result }).ok();
+                    }
+                    _ = cancel.cancelled() => tracing::info!("Upload of {} cancelled", job.path.display()),
+                }
+            });
+        }
+        self.running.extend(started);
+    }
+
+    /// Removes a finished job or schedules its next attempt
+    pub fn finish(&mut self, id: u64, result: Result<String>, sender: &UnboundedSender<RequestResponse>) {
+        self.running.remove(&id);
+        sender.send(RequestResponse::UploadFinished(id)).ok();
+        // cancelled while the upload was finishing
+        let Some(job) = self.jobs.iter_mut().find(|j| j.id == id) else {
+            return;
+        };
+        match result {
+            Ok(url) => {
+                self.jobs.retain(|j| j.id != id);
+                sender.send(RequestResponse::HistoryUpdated).ok();
+                sender
+                    .send(RequestResponse::Toast(Toast::success(format!("Image uploaded {url}"))))
+                    .ok();
+            }
+            Err(e) => {
+                job.attempts += 1;
+                let retryable = e.downcast_ref::<UploadError>().is_some_and(UploadError::is_retryable);
+                if !retryable || job.attempts >= MAX_ATTEMPTS || !job.path.exists() {
+                    tracing::error!("Giving up on uploading {} {e:?}", job.path.display());
+                    job.state = JobState::Failed(e.to_string());
+                    sender
+                        .send(RequestResponse::Toast(Toast::error(format!(
+                            "Failed uploading image\n{e}"
+                        ))))
+                        .ok();
+                } else {
+                    let delay = backoff(job.attempts);
+                    tracing::warn!(
+                        "Upload of {} failed, retrying in {}s {e:?}",
+                        job.path.display(),
+                        delay.as_secs()
+                    );
+                    job.next_attempt = chrono::Utc::now().timestamp_millis() + delay.as_millis() as i64;
+                    sender
+                        .send(RequestResponse::Toast(Toast::info(format!(
+                            "Upload failed, retrying in {}s\n{e}",
+                            delay.as_secs()
+                        ))))
+                        .ok();
+                }
+            }
+        }
+        self.changed(sender);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let delays: Vec<u64> = (1..=MAX_ATTEMPTS).map(|attempts| backoff(attempts).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 16, 32]);
+        assert_eq!(backoff(0), BASE_DELAY);
+        assert_eq!(backoff(9), MAX_DELAY);
+        assert_eq!(backoff(u32::MAX), MAX_DELAY);
+    }
+}
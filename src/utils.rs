@@ -1,4 +1,9 @@
-use std::{env, path::PathBuf};
+use std::{
+    env,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
 
 use home::home_dir;
 use notify_rust::Notification;
@@ -12,6 +17,29 @@ pub fn ascella_dir() -> PathBuf {
     }
 }
 
+/// Splits a command like a shell would and replaces `{file}` in every argument, so paths with spaces stay a single
+/// argument
+pub fn split_command(command: &str, file: &str) -> anyhow::Result<Vec<String>> {
+    Ok(shell_words::split(command)
+        .map_err(|e| anyhow::anyhow!("Invalid command {command} {e}"))?
+        .into_iter()
+        .map(|arg| arg.replace("{file}", file))
+        .collect())
+}
+
+/// Whether the program can be found in one of the PATH directories
+pub fn on_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+/// Turns a leading `~` into the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest.trim_start_matches('/')),
+        _ => PathBuf::from(path),
+    }
+}
+
 pub fn theme_number_to_theme(theme: u8) -> Theme {
     match theme {
         0 => themes::DARK_THEME,
@@ -46,3 +74,92 @@ pub fn theme_to_name(theme: u8) -> String {
 pub fn ascella_notif() -> Notification {
     Notification::new().summary("Ascella").clone()
 }
+
+/// Guess the mime type of a file from its magic bytes, falling back to the extension
+pub fn infer_mime(path: &Path) -> io::Result<String> {
+    let mut head = Vec::with_capacity(8192);
+    File::open(path)?.take(8192).read_to_end(&mut head)?;
+    Ok(mime_from_bytes(path, &head))
+}
+
+pub fn mime_from_bytes(path: &Path, head: &[u8]) -> String {
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type().to_string();
+    }
+    if let Some(mime) = mime_guess::from_path(path).first_raw() {
+        return mime.to_string();
+    }
+    // a cut off multibyte character at the end of the buffer still counts as text
+    let is_text = match std::str::from_utf8(head) {
+        Ok(_) => !head.is_empty(),
+        Err(e) => e.error_len().is_none(),
+    };
+    if is_text {
+        "text/plain".to_string()
+    } else {
+        "application/octet-stream".to_string()
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} {}", UNITS[0])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_command_keeps_the_file_one_argument() {
+        assert_eq!(
+            split_command("tesseract {file} - tsv", "/tmp/a b.png").unwrap(),
+            ["tesseract", "/tmp/a b.png", "-", "tsv"]
+        );
+        assert_eq!(
+            split_command("convert \"{file}\" out/{file}.jpg", "a b").unwrap(),
+            ["convert", "a b", "out/a b.jpg"]
+        );
+        assert!(split_command("ocr \"unclosed", "a").is_err());
+    }
+
+    #[test]
+    fn mime_from_magic_bytes() {
+        let unnamed = Path::new("upload");
+        assert_eq!(mime_from_bytes(unnamed, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(mime_from_bytes(unnamed, b"\xff\xd8\xff\xe0\0\x10JFIF"), "image/jpeg");
+        assert_eq!(mime_from_bytes(unnamed, b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(mime_from_bytes(unnamed, b"PK\x03\x04\x14\0\0\0"), "application/zip");
+        // the content wins over a wrong extension
+        assert_eq!(
+            mime_from_bytes(Path::new("shot.txt"), b"GIF89a\x01\0\x01\0"),
+            "image/gif"
+        );
+    }
+
+    #[test]
+    fn mime_fallbacks() {
+        assert_eq!(mime_from_bytes(Path::new("notes.md"), b"# title"), "text/markdown");
+        assert_eq!(mime_from_bytes(Path::new("notes"), b"plain text"), "text/plain");
+        // cut off in the middle of a multibyte character
+        assert_eq!(
+            mime_from_bytes(Path::new("notes"), &"h\u{e9}".as_bytes()[..2]),
+            "text/plain"
+        );
+        assert_eq!(
+            mime_from_bytes(Path::new("blob"), b"\0\xff\xfe\x01"),
+            "application/octet-stream"
+        );
+        assert_eq!(mime_from_bytes(Path::new("empty"), b""), "application/octet-stream");
+    }
+}
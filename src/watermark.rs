@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Result};
+use eframe::egui::{pos2, Color32};
+use image::{imageops, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{text, utils::expand_home};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPosition {
+    pub const ALL: [WatermarkPosition; 5] = [
+        Self::TopLeft,
+        Self::TopRight,
+        Self::BottomLeft,
+        Self::BottomRight,
+        Self::Center,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TopLeft => "Top left",
+            Self::TopRight => "Top right",
+            Self::BottomLeft => "Bottom left",
+            Self::BottomRight => "Bottom right",
+            Self::Center => "Center",
+        }
+    }
+
+    /// Top left corner of a `width` x `height` watermark on a `bounds` sized image
+    fn place(&self, bounds: (u32, u32), width: u32, height: u32, margin: u32) -> (i64, i64) {
+        let right = bounds.0 as i64 - width as i64 - margin as i64;
+        let bottom = bounds.1 as i64 - height as i64 - margin as i64;
+        match self {
+            Self::TopLeft => (margin as i64, margin as i64),
+            Self::TopRight => (right, margin as i64),
+            Self::BottomLeft => (margin as i64, bottom),
+            Self::BottomRight => (right, bottom),
+            Self::Center => (
+                (bounds.0 as i64 - width as i64) / 2,
+                (bounds.1 as i64 - height as i64) / 2,
+            ),
+        }
+    }
+}
+
+/// Branding composited onto screenshots by the watermark pipeline stage
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WatermarkConfig {
+    pub text: String,
+    /// image used instead of the text when set
+    pub image: String,
+    pub position: WatermarkPosition,
+    /// 0-100
+    pub opacity: u8,
+    /// width of the watermark in percent of the screenshot width
+    pub scale: f32,
+    /// distance to the edges in pixels
+    pub margin: u32,
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            image: String::new(),
+            position: WatermarkPosition::BottomRight,
+            opacity: 70,
+            scale: 15.0,
+            margin: 10,
+        }
+    }
+}
+
+impl WatermarkConfig {
+    pub fn apply(&self, image: &mut RgbaImage) -> Result<()> {
+        let opacity = self.opacity.min(100) as f32 / 100.0;
+        let target_width = (image.width() as f32 * self.scale / 100.0).max(1.0);
+
+        if !self.image.is_empty() {
+            let mut mark = image::open(expand_home(&self.image))
+                .map_err(|e| anyhow!("Failed opening watermark {} {e}", self.image))?
+                .to_rgba8();
+            let target_height = (mark.height() as f32 * target_width / mark.width() as f32).max(1.0);
+            mark = imageops::resize(
+                &mark,
+                target_width as u32,
+                target_height as u32,
+                imageops::FilterType::Lanczos3,
+            );
+            for pixel in mark.pixels_mut() {
+                pixel[3] = (pixel[3] as f32 * opacity) as u8;
+            }
+            let (x, y) = self
+                .position
+                .place(image.dimensions(), mark.width(), mark.height(), self.margin);
+            imageops::overlay(image, &mark, x, y);
+        } else if !self.text.is_empty() {
+            let (width_at_100, _) = text::measure_text(&self.text, 100.0);
+            let size = 100.0 * target_width / width_at_100.max(1.0);
+            let (width, height) = text::measure_text(&self.text, size);
+            let (x, y) = self.position.place(
+                image.dimensions(),
+                width.ceil() as u32,
+                height.ceil() as u32,
+                self.margin,
+            );
+            let alpha = (opacity * 255.0) as u8;
+            let (x, y) = (x as f32, y as f32);
+            let shadow = Color32::from_rgba_unmultiplied(0, 0, 0, alpha / 2);
+            text::draw_text(image, pos2(x + 1.0, y + 1.0), &self.text, shadow, size);
+            let color = Color32::from_rgba_unmultiplied(255, 255, 255, alpha);
+            text::draw_text(image, pos2(x, y), &self.text, color, size);
+        }
+        Ok(())
+    }
+}